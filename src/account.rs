@@ -0,0 +1,302 @@
+use crate::{
+    serialize::{Serialize, SerializeError},
+    AuthVerifier, Challenge, Nonce, PasswordChange, PasswordError, PasswordPolicy, Proof, UserID,
+    USER_ID_SIZE,
+};
+use std::convert::TryInto;
+
+/// Number of failed logins in a row after which account is locked.
+pub const MAX_FAILED_LOGINS: u32 = 5;
+
+/// Server side account record. It holds credentials and security state and must never be sent to
+/// client. Data that client can see is kept in `UserProfile`. Password is never stored, only
+/// login verifier created by client.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Account {
+    id: UserID,
+    verifier: AuthVerifier,
+    failed_logins: u32,
+    locked: bool,
+}
+
+impl Account {
+    // id = 8, login verifier, failed logins = 4 and locked = 1.
+    const BYTE_LEN: usize = USER_ID_SIZE + AuthVerifier::BYTE_LEN + 4 + 1;
+
+    /// Creates new unlocked account with verifier received in `Comm::Register`.
+    pub fn new(id: UserID, verifier: AuthVerifier) -> Self {
+        Self {
+            id,
+            verifier,
+            failed_logins: 0,
+            locked: false,
+        }
+    }
+
+    /// Creates new unlocked account with `password`, e.g. by server administrator. Returns
+    /// `PasswordError` if password doesn't follow `policy`.
+    pub fn with_password(
+        id: UserID,
+        password: &str,
+        policy: &PasswordPolicy,
+    ) -> Result<Self, PasswordError> {
+        policy.validate(password)?;
+        Ok(Self::new(id, AuthVerifier::new(password)))
+    }
+
+    /// Returns UserID number.
+    pub fn id(&self) -> UserID {
+        self.id
+    }
+
+    /// Returns verifier used to check login proofs.
+    pub fn verifier(&self) -> &AuthVerifier {
+        &self.verifier
+    }
+
+    /// Returns true if `password` is account password. It doesn't change security state.
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.verifier.check_password(password)
+    }
+
+    /// Replaces password with `password` without checking current one. Returns `PasswordError`
+    /// if password doesn't follow `policy`.
+    pub fn set_password(
+        &mut self,
+        password: &str,
+        policy: &PasswordPolicy,
+    ) -> Result<(), PasswordError> {
+        policy.validate(password)?;
+        self.verifier = AuthVerifier::new(password);
+        Ok(())
+    }
+
+    /// Replaces verifier with one received in `Comm::ChangePassword` if its proof of current
+    /// password is valid. `nonce` is the one sent in `Comm::Connected` of this connection, see
+    /// `PasswordChange::open`. Returns false if proof isn't valid.
+    pub fn change_password(&mut self, change: &PasswordChange, nonce: &Nonce) -> bool {
+        match change.open(&self.verifier, self.id, nonce) {
+            Some(verifier) => {
+                self.verifier = verifier;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks `proof` received in `Comm::Login` against `challenge` and updates security state.
+    /// Challenge is used up even if account is locked. Locked account can't log in. After
+    /// `MAX_FAILED_LOGINS` failed attempts in a row account is locked.
+    pub fn login(&mut self, challenge: &mut Challenge, proof: &Proof) -> bool {
+        let valid = challenge.verify(&self.verifier, self.id, proof);
+        if self.locked {
+            return false;
+        }
+
+        if valid {
+            self.failed_logins = 0;
+            true
+        } else {
+            self.failed_logins += 1;
+            self.locked = self.failed_logins >= MAX_FAILED_LOGINS;
+            false
+        }
+    }
+
+    /// Returns number of failed logins since last successful one.
+    pub fn failed_logins(&self) -> u32 {
+        self.failed_logins
+    }
+
+    /// Returns true if account is locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Locks account, so nobody can log in.
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    /// Unlocks account and resets failed logins counter.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+        self.failed_logins = 0;
+    }
+}
+
+impl Serialize for Account {
+    fn encoded_len(&self) -> usize {
+        Account::BYTE_LEN
+    }
+
+    /// Writes account for server storage. It must never be sent to client.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < Account::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let mut index = crate::write_bytes_to_buffer(buffer, &self.id.to_le_bytes())?;
+        index += self.verifier.serialize(&mut buffer[index..])?;
+        index +=
+            crate::write_bytes_to_buffer(&mut buffer[index..], &self.failed_logins.to_le_bytes())?;
+        buffer[index] = self.locked as u8;
+
+        Ok(index + 1)
+    }
+
+    /// Reads account written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < Account::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let id = crate::parse_id_from_bytes(buffer)?;
+        let mut index = USER_ID_SIZE;
+        let (verifier, len) = AuthVerifier::deserialize(&buffer[index..])?;
+        index += len;
+        let failed_logins = u32::from_le_bytes(buffer[index..index + 4].try_into().unwrap());
+        index += 4;
+        let locked = match buffer[index] {
+            0 => false,
+            1 => true,
+            sig => return Err(SerializeError::UnknownSignature(sig)),
+        };
+
+        let account = Self {
+            id,
+            verifier,
+            failed_logins,
+            locked,
+        };
+        Ok((account, index + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{login_proof, new_nonce, AuthParams};
+
+    /// Creates cheap login verifier, because tests compute a lot of proofs.
+    fn verifier(password: &str) -> AuthVerifier {
+        AuthVerifier::with_params(password, AuthParams::with_iterations(1_000))
+    }
+
+    /// Answers new challenge with `password` and tries to log in.
+    fn login(account: &mut Account, password: &str) -> bool {
+        let mut challenge = Challenge::new();
+        let proof = login_proof(
+            account.id(),
+            password,
+            account.verifier().params(),
+            challenge.nonce(),
+        );
+        account.login(&mut challenge, &proof)
+    }
+
+    #[test]
+    fn change_password() {
+        let original_password = "abcd";
+        let new_password = "new_password";
+        let mut account = Account::new(0, verifier(original_password));
+        let nonce = new_nonce();
+        let change = |password, new| {
+            PasswordChange::with_params(
+                0,
+                password,
+                account.verifier().params(),
+                &nonce,
+                verifier(new),
+            )
+        };
+        let bad_change = change("bad_password", new_password);
+        let valid_change = change(original_password, new_password);
+
+        // We shouldn't be able to change password if we don't provide old one correct
+        assert!(!account.change_password(&bad_change, &nonce));
+        assert!(account.verify_password(original_password));
+
+        // Change can't be replayed in other connection
+        assert!(!account.change_password(&valid_change, &new_nonce()));
+        assert!(account.verify_password(original_password));
+
+        // When we provide proper old password then we can change it to new one
+        assert!(account.change_password(&valid_change, &nonce));
+        assert!(!account.verify_password(original_password));
+        assert!(account.verify_password(new_password));
+        assert!(login(&mut account, new_password));
+        assert!(!login(&mut account, original_password));
+    }
+
+    #[test]
+    fn lock_after_failed_logins() {
+        let mut account = Account::new(0, verifier("abcd"));
+        for _ in 1..MAX_FAILED_LOGINS {
+            assert!(!login(&mut account, "bad"));
+        }
+        assert!(login(&mut account, "abcd"));
+        assert_eq!(account.failed_logins(), 0);
+
+        for _ in 0..MAX_FAILED_LOGINS {
+            assert!(!login(&mut account, "bad"));
+        }
+        assert!(account.is_locked());
+        assert!(!login(&mut account, "abcd"));
+
+        account.unlock();
+        assert!(login(&mut account, "abcd"));
+    }
+
+    #[test]
+    fn password_policy() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            Account::with_password(0, "ab\0cd", &policy),
+            Err(PasswordError::ContainsNul)
+        );
+        assert_eq!(
+            Account::with_password(0, &"a".repeat(crate::MAX_PASS_BYTE_LEN + 1), &policy),
+            Err(PasswordError::TooLong {
+                max: crate::MAX_PASS_BYTE_LEN,
+                actual: crate::MAX_PASS_BYTE_LEN + 1
+            })
+        );
+
+        let policy = PasswordPolicy {
+            min_char_len: 1,
+            max_byte_len: 40,
+        };
+        let mut account = Account::with_password(0, "a", &policy).unwrap();
+        assert!(account.verify_password("a"));
+        assert_eq!(account.set_password(&"b".repeat(40), &policy), Ok(()));
+        assert!(account.verify_password(&"b".repeat(40)));
+    }
+
+    #[test]
+    fn store_and_load() {
+        // Only verifier with valid parameters can be stored.
+        let mut account = Account::new(7, AuthVerifier::new("abcd"));
+        login(&mut account, "bad");
+        account.lock();
+
+        let mut buffer = [0u8; Account::BYTE_LEN];
+        account.serialize(&mut buffer).unwrap();
+        let loaded = Account::deserialize(&buffer).unwrap().0;
+        assert_eq!(loaded, account);
+        assert!(loaded.verify_password("abcd"));
+        assert_eq!(loaded.verifier(), account.verifier());
+
+        for short in 0..Account::BYTE_LEN {
+            assert_eq!(
+                Account::deserialize(&buffer[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+            assert_eq!(
+                account.serialize(&mut vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+    }
+}
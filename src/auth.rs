@@ -0,0 +1,711 @@
+use crate::{
+    serialize::{Serialize, SerializeError},
+    UserID,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use subtle::ConstantTimeEq;
+
+/// Size of challenge nonce in bytes.
+pub const NONCE_LEN: usize = 32;
+
+/// Size of login proof and stored key in bytes.
+pub const PROOF_LEN: usize = 32;
+
+/// Number of PBKDF2 iterations used by `AuthParams::new`. It is also the lowest number accepted in
+/// `AuthParams`, so malicious server can't make proof easy to crack. Iterations are stored with
+/// verifier, so new verifiers can use more of them without invalidating old ones.
+pub const AUTH_ITERATIONS: u32 = 100_000;
+
+/// Highest number of PBKDF2 iterations accepted in `AuthParams`, so malicious server can't make
+/// client compute proof forever.
+pub const MAX_AUTH_ITERATIONS: u32 = 10 * AUTH_ITERATIONS;
+
+/// Size of random salt of `AuthVerifier` in bytes.
+pub const AUTH_SALT_LEN: usize = 16;
+
+/// Random challenge send by server.
+pub type Nonce = [u8; NONCE_LEN];
+
+/// Answer to challenge send by client.
+pub type Proof = [u8; PROOF_LEN];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Returns new random nonce.
+///
+/// # Panics
+/// This function will panic if operating system can't provide random bytes.
+pub fn new_nonce() -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).expect("Can't generate random nonce.");
+    nonce
+}
+
+/// Returns HMAC-SHA256 of all `parts` joined together.
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; PROOF_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts key of any size.");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Key derivation parameters of single account. Server stores them in `AuthVerifier` and sends
+/// them in `Comm::AuthParams`, because client needs them to compute `login_proof`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuthParams {
+    /// Random salt of account.
+    pub salt: [u8; AUTH_SALT_LEN],
+    /// Number of PBKDF2 iterations.
+    pub iterations: u32,
+}
+
+impl AuthParams {
+    // Salt and iterations = 4.
+    pub(crate) const BYTE_LEN: usize = AUTH_SALT_LEN + 4;
+
+    /// Creates parameters with random salt and `AUTH_ITERATIONS`.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn new() -> Self {
+        Self::with_iterations(AUTH_ITERATIONS)
+    }
+
+    /// Creates parameters with random salt and given number of iterations. Parameters with
+    /// iterations out of `AUTH_ITERATIONS..=MAX_AUTH_ITERATIONS` can be used only locally, they
+    /// can't be serialized.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn with_iterations(iterations: u32) -> Self {
+        let mut salt = [0u8; AUTH_SALT_LEN];
+        getrandom::getrandom(&mut salt).expect("Can't generate random salt.");
+        Self { salt, iterations }
+    }
+
+    /// Returns parameters that server sends when asked about user `id` that doesn't exist, so
+    /// answer doesn't reveal which users exist. They are always the same for given `id` and
+    /// server `secret`.
+    pub fn decoy(id: UserID, secret: &[u8]) -> Self {
+        let salt = hmac(secret, &[b"talk-common decoy salt", &id.to_le_bytes()]);
+        Self {
+            salt: salt[..AUTH_SALT_LEN].try_into().unwrap(),
+            iterations: AUTH_ITERATIONS,
+        }
+    }
+
+    /// Returns true if number of iterations is between `AUTH_ITERATIONS` and
+    /// `MAX_AUTH_ITERATIONS`.
+    pub fn is_valid(&self) -> bool {
+        (AUTH_ITERATIONS..=MAX_AUTH_ITERATIONS).contains(&self.iterations)
+    }
+
+    /// Returns error for parameters that break rules of `is_valid`.
+    fn invalid_iterations() -> SerializeError {
+        SerializeError::InvalidValue {
+            type_name: "AuthParams".into(),
+            offset: AUTH_SALT_LEN,
+        }
+    }
+}
+
+impl Default for AuthParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serialize for AuthParams {
+    fn encoded_len(&self) -> usize {
+        AuthParams::BYTE_LEN
+    }
+
+    /// Writes salt followed by iterations. Returns `SerializeError::InvalidValue` if they break
+    /// rules of `AuthParams::is_valid`, because peer would reject them.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if !self.is_valid() {
+            return Err(AuthParams::invalid_iterations());
+        }
+        if buffer.len() < AuthParams::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let index = crate::write_bytes_to_buffer(buffer, &self.salt)?;
+        Ok(index
+            + crate::write_bytes_to_buffer(&mut buffer[index..], &self.iterations.to_le_bytes())?)
+    }
+
+    /// Reads parameters written by `serialize`. Returns `SerializeError::InvalidValue` if they break
+    /// rules of `AuthParams::is_valid`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < AuthParams::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let params = Self {
+            salt: buffer[..AUTH_SALT_LEN].try_into().unwrap(),
+            iterations: u32::from_le_bytes(
+                buffer[AUTH_SALT_LEN..AuthParams::BYTE_LEN]
+                    .try_into()
+                    .unwrap(),
+            ),
+        };
+        if !params.is_valid() {
+            return Err(AuthParams::invalid_iterations());
+        }
+        Ok((params, AuthParams::BYTE_LEN))
+    }
+}
+
+/// Derives `ClientKey` from password and account parameters.
+fn client_key(password: &str, params: &AuthParams) -> [u8; PROOF_LEN] {
+    let mut salted_password = [0u8; PROOF_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(
+        password.as_bytes(),
+        &params.salt,
+        params.iterations,
+        &mut salted_password,
+    );
+    hmac(&salted_password, &[b"Client Key"])
+}
+
+/// Returns signature binding stored key to this particular challenge and user.
+fn client_signature(stored_key: &[u8], id: UserID, nonce: &Nonce) -> [u8; PROOF_LEN] {
+    hmac(stored_key, &[nonce, &id.to_le_bytes()])
+}
+
+/// Returns `a XOR b`.
+fn xor(a: &[u8; PROOF_LEN], b: &[u8; PROOF_LEN]) -> [u8; PROOF_LEN] {
+    let mut out = [0u8; PROOF_LEN];
+    for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(b.iter())) {
+        *o = a ^ b;
+    }
+    out
+}
+
+/// Computes proof from `ClientKey` of user `id` for `nonce`.
+fn proof_from_key(client_key: &[u8; PROOF_LEN], id: UserID, nonce: &Nonce) -> Proof {
+    let stored_key: [u8; PROOF_LEN] = Sha256::digest(client_key).into();
+    xor(client_key, &client_signature(&stored_key, id, nonce))
+}
+
+/// Computes proof that client knows password of user `id` with `params` received in
+/// `Comm::AuthParams`. It is valid only for `nonce`. Received parameters are always valid, because
+/// `AuthParams::deserialize` rejects too few or too many iterations.
+pub fn login_proof(id: UserID, password: &str, params: &AuthParams, nonce: &Nonce) -> Proof {
+    proof_from_key(&client_key(password, params), id, nonce)
+}
+
+/// Data that server stores instead of password to check login proofs. Server sends random `Nonce`
+/// in `Comm::Connected` and client answers with `Comm::Login` containing `Proof` computed by
+/// `login_proof`, so password itself is never transmitted.
+///
+/// It works like SCRAM-SHA-256: `ClientKey = HMAC(PBKDF2(password, salt), "Client Key")`,
+/// `StoredKey = SHA256(ClientKey)` and `Proof = ClientKey XOR HMAC(StoredKey, nonce || id)`.
+/// Verifier holds only `AuthParams` with random salt and `StoredKey`, which can't be used to log
+/// in. Client gets parameters with `Comm::RequestAuthParams` before it computes proof.
+///
+/// # Security
+/// Client creates verifier and sends it in `Comm::Register` without any encryption, because it
+/// doesn't share any secret with server yet. Anybody who sees verifier can guess password
+/// offline, and together with any later `Comm::Login` of the same user it reveals `ClientKey`,
+/// which is enough to log in as that user. `Comm::Register` must be sent only over encrypted
+/// transport, e.g. TLS, because this crate doesn't encrypt connections. Verifier must also be
+/// kept secret by server. `Comm::ChangePassword` sends new verifier encrypted, see
+/// `PasswordChange`.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuthVerifier {
+    pub(crate) params: AuthParams,
+    pub(crate) stored_key: [u8; PROOF_LEN],
+}
+
+impl AuthVerifier {
+    /// Size of verifier in bytes.
+    pub const BYTE_LEN: usize = AuthParams::BYTE_LEN + PROOF_LEN;
+
+    /// Creates verifier for `password` with random salt and `AUTH_ITERATIONS`.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn new(password: &str) -> Self {
+        Self::with_params(password, AuthParams::new())
+    }
+
+    /// Creates verifier for `password` with given parameters.
+    pub fn with_params(password: &str, params: AuthParams) -> Self {
+        Self {
+            stored_key: Sha256::digest(client_key(password, &params)).into(),
+            params,
+        }
+    }
+
+    /// Returns parameters that client needs to compute `login_proof`.
+    pub fn params(&self) -> &AuthParams {
+        &self.params
+    }
+
+    /// Returns true if `proof` was computed with valid password for `id` and `nonce`. Hashes are
+    /// compared in constant time.
+    pub fn verify(&self, id: UserID, nonce: &Nonce, proof: &Proof) -> bool {
+        self.client_key(id, nonce, proof).is_some()
+    }
+
+    /// Returns `ClientKey` recovered from `proof` if it is valid for `id` and `nonce`.
+    fn client_key(&self, id: UserID, nonce: &Nonce, proof: &Proof) -> Option<[u8; PROOF_LEN]> {
+        let client_key = xor(proof, &client_signature(&self.stored_key, id, nonce));
+        let stored_key: [u8; PROOF_LEN] = Sha256::digest(client_key).into();
+        if bool::from(stored_key.ct_eq(&self.stored_key)) {
+            Some(client_key)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if verifier was created for `password`. Hashes are compared in constant time.
+    pub fn check_password(&self, password: &str) -> bool {
+        let stored_key: [u8; PROOF_LEN] = Sha256::digest(client_key(password, &self.params)).into();
+        stored_key.ct_eq(&self.stored_key).into()
+    }
+}
+
+/// New verifier that client sends in `Comm::ChangePassword` with proof of current password.
+/// Stored key of new verifier is encrypted with key derived from `ClientKey` of current password
+/// and nonce of connection. Server recovers `ClientKey` when it checks proof, so eavesdropper sees
+/// only salt and iterations of new verifier. It stays secret as long as verifier of current
+/// password is secret, see `AuthVerifier`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PasswordChange {
+    pub(crate) params: AuthParams,
+    pub(crate) encrypted_key: [u8; PROOF_LEN],
+    pub(crate) proof: Proof,
+}
+
+impl PasswordChange {
+    /// Size of password change in bytes.
+    pub const BYTE_LEN: usize = AuthParams::BYTE_LEN + 2 * PROOF_LEN;
+
+    /// Creates change of password of user `id` to `new_password` with random salt and
+    /// `AUTH_ITERATIONS`. `params` are current parameters received in `Comm::AuthParams` and
+    /// `nonce` is the one received in `Comm::Connected`.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn new(
+        id: UserID,
+        old_password: &str,
+        params: &AuthParams,
+        nonce: &Nonce,
+        new_password: &str,
+    ) -> Self {
+        Self::with_params(
+            id,
+            old_password,
+            params,
+            nonce,
+            AuthVerifier::new(new_password),
+        )
+    }
+
+    /// Creates change of password of user `id` to `verifier`.
+    pub fn with_params(
+        id: UserID,
+        old_password: &str,
+        params: &AuthParams,
+        nonce: &Nonce,
+        verifier: AuthVerifier,
+    ) -> Self {
+        let client_key = client_key(old_password, params);
+        let encrypted_key = xor(&verifier.stored_key, &change_key(&client_key, nonce));
+        let proof = proof_from_key(
+            &client_key,
+            id,
+            &change_nonce(nonce, &verifier.params, &encrypted_key),
+        );
+        Self {
+            params: verifier.params,
+            encrypted_key,
+            proof,
+        }
+    }
+
+    /// Returns parameters of new verifier.
+    pub fn params(&self) -> &AuthParams {
+        &self.params
+    }
+
+    /// Returns new verifier if proof was computed with password of `current` verifier of user
+    /// `id`. `nonce` is the one sent in `Comm::Connected` of this connection, so recorded change
+    /// can't be replayed in other connection. Returns `None` if proof isn't valid.
+    pub fn open(&self, current: &AuthVerifier, id: UserID, nonce: &Nonce) -> Option<AuthVerifier> {
+        let proof_nonce = change_nonce(nonce, &self.params, &self.encrypted_key);
+        let client_key = current.client_key(id, &proof_nonce, &self.proof)?;
+        Some(AuthVerifier {
+            params: self.params,
+            stored_key: xor(&self.encrypted_key, &change_key(&client_key, nonce)),
+        })
+    }
+}
+
+/// Returns key that encrypts stored key of new verifier in `PasswordChange`.
+fn change_key(client_key: &[u8; PROOF_LEN], nonce: &Nonce) -> [u8; PROOF_LEN] {
+    hmac(client_key, &[b"talk-common change password key", nonce])
+}
+
+/// Returns nonce for proof of current password in `PasswordChange`. It binds proof to new
+/// verifier and to connection in which server sent `nonce`, so recorded request can't be
+/// replayed with other verifier or in other connection.
+fn change_nonce(nonce: &Nonce, params: &AuthParams, encrypted_key: &[u8; PROOF_LEN]) -> Nonce {
+    hmac(
+        nonce,
+        &[
+            b"talk-common change password",
+            &params.salt,
+            &params.iterations.to_le_bytes(),
+            encrypted_key,
+        ],
+    )
+}
+
+impl Serialize for PasswordChange {
+    fn encoded_len(&self) -> usize {
+        PasswordChange::BYTE_LEN
+    }
+
+    /// Writes parameters of new verifier followed by its encrypted key and proof.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < PasswordChange::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let mut index = self.params.serialize(buffer)?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.encrypted_key)?;
+        Ok(index + crate::write_bytes_to_buffer(&mut buffer[index..], &self.proof)?)
+    }
+
+    /// Reads password change written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < PasswordChange::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let (params, index) = AuthParams::deserialize(buffer)?;
+        let change = Self {
+            params,
+            encrypted_key: buffer[index..index + PROOF_LEN].try_into().unwrap(),
+            proof: buffer[index + PROOF_LEN..PasswordChange::BYTE_LEN]
+                .try_into()
+                .unwrap(),
+        };
+        Ok((change, PasswordChange::BYTE_LEN))
+    }
+}
+
+impl Serialize for AuthVerifier {
+    fn encoded_len(&self) -> usize {
+        AuthVerifier::BYTE_LEN
+    }
+
+    /// Writes parameters followed by stored key.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < AuthVerifier::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let index = self.params.serialize(buffer)?;
+        Ok(index + crate::write_bytes_to_buffer(&mut buffer[index..], &self.stored_key)?)
+    }
+
+    /// Reads verifier written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < AuthVerifier::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let (params, index) = AuthParams::deserialize(buffer)?;
+        let verifier = Self {
+            params,
+            stored_key: buffer[index..AuthVerifier::BYTE_LEN].try_into().unwrap(),
+        };
+        Ok((verifier, AuthVerifier::BYTE_LEN))
+    }
+}
+
+// Stored key is not printed, so it won't end in logs.
+impl std::fmt::Debug for AuthVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthVerifier")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Server side state of single challenge. Nonce can be used only once, so recorded login can't be
+/// replayed.
+#[derive(Debug)]
+pub struct Challenge {
+    nonce: Nonce,
+    used: bool,
+}
+
+impl Challenge {
+    /// Creates challenge with new random nonce.
+    pub fn new() -> Self {
+        Self {
+            nonce: new_nonce(),
+            used: false,
+        }
+    }
+
+    /// Returns nonce that must be send to client.
+    pub fn nonce(&self) -> &Nonce {
+        &self.nonce
+    }
+
+    /// Returns true if challenge was already used.
+    pub fn is_used(&self) -> bool {
+        self.used
+    }
+
+    /// Checks `proof` for user `id`. Challenge is used up by first call, even if proof was
+    /// invalid, so every next call returns false.
+    pub fn verify(&mut self, verifier: &AuthVerifier, id: UserID, proof: &Proof) -> bool {
+        if self.used {
+            return false;
+        }
+
+        self.used = true;
+        verifier.verify(id, &self.nonce, proof)
+    }
+}
+
+impl Default for Challenge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests compute a lot of proofs, so they use cheaper derivation than `AUTH_ITERATIONS`.
+    const ITERATIONS: u32 = 1_000;
+
+    fn verifier(password: &str) -> AuthVerifier {
+        AuthVerifier::with_params(password, AuthParams::with_iterations(ITERATIONS))
+    }
+
+    fn proof(id: UserID, password: &str, verifier: &AuthVerifier, nonce: &Nonce) -> Proof {
+        login_proof(id, password, verifier.params(), nonce)
+    }
+
+    #[test]
+    fn valid_proof() {
+        let verifier = verifier("abcd");
+        let mut challenge = Challenge::new();
+        let proof = proof(1, "abcd", &verifier, challenge.nonce());
+
+        assert!(challenge.verify(&verifier, 1, &proof));
+    }
+
+    #[test]
+    fn default_iterations() {
+        let verifier = AuthVerifier::new("abcd");
+        assert_eq!(verifier.params().iterations, AUTH_ITERATIONS);
+        let nonce = new_nonce();
+        let proof = login_proof(1, "abcd", verifier.params(), &nonce);
+
+        assert!(verifier.verify(1, &nonce, &proof));
+    }
+
+    #[test]
+    fn bad_password() {
+        let verifier = verifier("abcd");
+        let mut challenge = Challenge::new();
+        let proof = proof(1, "abce", &verifier, challenge.nonce());
+
+        assert!(!challenge.verify(&verifier, 1, &proof));
+    }
+
+    #[test]
+    fn other_user() {
+        // Proof is bound to user, so it can't be used to log in as somebody else.
+        let verifier = verifier("abcd");
+        let nonce = new_nonce();
+        let proof = proof(2, "abcd", &verifier, &nonce);
+
+        assert!(!verifier.verify(1, &nonce, &proof));
+        assert!(verifier.verify(2, &nonce, &proof));
+    }
+
+    #[test]
+    fn random_salt() {
+        // Verifiers of the same password can't be compared or precomputed.
+        let v1 = verifier("abcd");
+        let v2 = verifier("abcd");
+        assert_ne!(v1.params().salt, v2.params().salt);
+        assert_ne!(v1.stored_key, v2.stored_key);
+
+        // Proof needs salt of account.
+        let nonce = new_nonce();
+        let proof = login_proof(1, "abcd", v2.params(), &nonce);
+        assert!(!v1.verify(1, &nonce, &proof));
+        assert!(v2.verify(1, &nonce, &proof));
+    }
+
+    #[test]
+    fn decoy_params() {
+        let decoy = AuthParams::decoy(1, b"secret");
+        assert_eq!(decoy, AuthParams::decoy(1, b"secret"));
+        assert_eq!(decoy.iterations, AUTH_ITERATIONS);
+        assert_ne!(decoy.salt, AuthParams::decoy(2, b"secret").salt);
+        assert_ne!(decoy.salt, AuthParams::decoy(1, b"other").salt);
+    }
+
+    #[test]
+    fn replay_with_other_nonce() {
+        let verifier = verifier("abcd");
+        let recorded = proof(1, "abcd", &verifier, &new_nonce());
+
+        let mut challenge = Challenge::new();
+        assert!(!challenge.verify(&verifier, 1, &recorded));
+    }
+
+    #[test]
+    fn replay_with_the_same_challenge() {
+        let verifier = verifier("abcd");
+        let mut challenge = Challenge::new();
+        let proof = proof(1, "abcd", &verifier, challenge.nonce());
+
+        assert!(challenge.verify(&verifier, 1, &proof));
+        assert!(challenge.is_used());
+        assert!(!challenge.verify(&verifier, 1, &proof));
+    }
+
+    #[test]
+    fn failed_attempt_uses_challenge() {
+        let verifier = verifier("abcd");
+        let mut challenge = Challenge::new();
+        let proof = proof(1, "abcd", &verifier, challenge.nonce());
+
+        assert!(!challenge.verify(&verifier, 1, &[0u8; PROOF_LEN]));
+        assert!(!challenge.verify(&verifier, 1, &proof));
+    }
+
+    #[test]
+    fn verifier_is_not_proof() {
+        // Stolen verifier can't be used as proof.
+        let verifier = verifier("abcd");
+        let nonce = new_nonce();
+        assert!(!verifier.verify(1, &nonce, &verifier.stored_key));
+    }
+
+    #[test]
+    fn store_and_load() {
+        // Only valid parameters can be stored, but key doesn't have to be derived.
+        let verifier = AuthVerifier {
+            params: AuthParams::with_iterations(AUTH_ITERATIONS),
+            stored_key: [7u8; PROOF_LEN],
+        };
+        let bytes = verifier.serialize_to_vec().unwrap();
+        assert_eq!(bytes.len(), AuthVerifier::BYTE_LEN);
+        assert_eq!(&bytes[..AUTH_SALT_LEN], &verifier.params().salt);
+        assert_eq!(
+            bytes[AUTH_SALT_LEN..AuthParams::BYTE_LEN],
+            AUTH_ITERATIONS.to_le_bytes()
+        );
+        assert_eq!(&bytes[AuthParams::BYTE_LEN..], &[7u8; PROOF_LEN]);
+
+        let loaded = AuthVerifier::deserialize_exact(&bytes).unwrap();
+        assert_eq!(loaded, verifier);
+        for short in 0..AuthVerifier::BYTE_LEN {
+            assert_eq!(
+                AuthVerifier::deserialize(&bytes[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+    }
+
+    #[test]
+    fn iteration_bounds() {
+        let err = SerializeError::InvalidValue {
+            type_name: "AuthParams".into(),
+            offset: AUTH_SALT_LEN,
+        };
+        for iterations in [0, 1, AUTH_ITERATIONS - 1, MAX_AUTH_ITERATIONS + 1, u32::MAX] {
+            let params = AuthParams::with_iterations(iterations);
+            assert!(!params.is_valid());
+            assert_eq!(params.serialize_to_vec(), Err(err.clone()));
+
+            let mut bytes = params.salt.to_vec();
+            bytes.extend_from_slice(&iterations.to_le_bytes());
+            assert_eq!(AuthParams::deserialize(&bytes), Err(err.clone()));
+        }
+
+        for iterations in [AUTH_ITERATIONS, MAX_AUTH_ITERATIONS] {
+            let params = AuthParams::with_iterations(iterations);
+            let bytes = params.serialize_to_vec().unwrap();
+            assert_eq!(AuthParams::deserialize_exact(&bytes), Ok(params));
+        }
+    }
+
+    #[test]
+    fn check_password() {
+        let verifier = verifier("abcd");
+        assert!(verifier.check_password("abcd"));
+        assert!(!verifier.check_password("abce"));
+    }
+
+    #[test]
+    fn password_change() {
+        let old = verifier("abcd");
+        let new = verifier("new_password");
+        let nonce = new_nonce();
+        let change = PasswordChange::with_params(1, "abcd", old.params(), &nonce, new.clone());
+
+        // Eavesdropper doesn't see new stored key.
+        assert_eq!(change.params(), new.params());
+        assert_ne!(change.encrypted_key, new.stored_key);
+        assert_eq!(change.open(&old, 1, &nonce), Some(new));
+
+        // Change needs current password and can't be replayed for other user or connection.
+        let bad = PasswordChange::with_params(1, "abce", old.params(), &nonce, verifier("x"));
+        assert_eq!(bad.open(&old, 1, &nonce), None);
+        assert_eq!(change.open(&old, 2, &nonce), None);
+        assert_eq!(change.open(&old, 1, &new_nonce()), None);
+
+        // New key can't be swapped.
+        let mut tampered = change.clone();
+        tampered.encrypted_key[0] ^= 1;
+        assert_eq!(tampered.open(&old, 1, &nonce), None);
+    }
+
+    #[test]
+    fn store_and_load_password_change() {
+        let change = PasswordChange {
+            params: AuthParams::with_iterations(AUTH_ITERATIONS),
+            encrypted_key: [7u8; PROOF_LEN],
+            proof: [8u8; PROOF_LEN],
+        };
+        let bytes = change.serialize_to_vec().unwrap();
+        assert_eq!(bytes.len(), PasswordChange::BYTE_LEN);
+        assert_eq!(PasswordChange::deserialize_exact(&bytes), Ok(change));
+        for short in 0..PasswordChange::BYTE_LEN {
+            assert_eq!(
+                PasswordChange::deserialize(&bytes[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+    }
+
+    #[test]
+    fn unique_nonces() {
+        assert_ne!(new_nonce(), new_nonce());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{frame::FRAME_HEADER_SIZE, Comm, FrameError, Serialize, ServerLimits};
+use bytes::{Buf, BufMut, BytesMut};
+use std::convert::TryInto;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Codec for `tokio_util::codec::Framed` that uses the same frame format as `FrameWriter` and
+/// `FrameReader`, so async and blocking peers can talk with each other.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TalkCodec {
+    limits: ServerLimits,
+}
+
+impl TalkCodec {
+    /// Creates codec that uses default `ServerLimits`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates codec that uses maximum frame size from `limits`.
+    pub fn with_limits(limits: ServerLimits) -> Self {
+        Self { limits }
+    }
+
+    /// Returns limits used by codec.
+    pub fn limits(&self) -> &ServerLimits {
+        &self.limits
+    }
+
+    /// Replaces limits, e.g. after `Comm::Limits` was received from server.
+    pub fn set_limits(&mut self, limits: ServerLimits) {
+        self.limits = limits;
+    }
+}
+
+impl Encoder<Comm> for TalkCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, item: Comm, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&item, dst)
+    }
+}
+
+impl Encoder<&Comm> for TalkCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, item: &Comm, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let buffer = item.serialize_to_vec()?;
+        if buffer.len() > self.limits.frame_len() {
+            return Err(FrameError::TooLarge(buffer.len(), self.limits.frame_len()));
+        }
+
+        dst.reserve(FRAME_HEADER_SIZE + buffer.len());
+        dst.put_u32_le(buffer.len() as u32);
+        dst.put_slice(&buffer);
+        Ok(())
+    }
+}
+
+impl Decoder for TalkCodec {
+    type Item = Comm;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let len = u32::from_le_bytes(src[..FRAME_HEADER_SIZE].try_into().unwrap()) as usize;
+        if len > self.limits.frame_len() {
+            return Err(FrameError::TooLarge(len, self.limits.frame_len()));
+        }
+
+        if src.len() < FRAME_HEADER_SIZE + len {
+            // Make room for the rest of frame, so it can be read with as few reads as possible.
+            src.reserve(FRAME_HEADER_SIZE + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(FRAME_HEADER_SIZE);
+        let frame = src.split_to(len);
+        Ok(Some(Comm::deserialize_exact(&frame)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FrameReader, FrameWriter, Message};
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{self, AsyncWriteExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn send_and_recive() {
+        // Small duplex buffer forces writer to wait until reader makes room.
+        let (client, server) = io::duplex(64);
+        let mut tx = FramedWrite::new(client, TalkCodec::new());
+        let mut rx = FramedRead::new(server, TalkCodec::new());
+
+        let sending = tokio::spawn(async move {
+            tx.send(Comm::Disconnected(1)).await.unwrap();
+            tx.send(Comm::Message(Message::new("Hi".to_string(), 1, 2)))
+                .await
+                .unwrap();
+            tx.send(Comm::Accepted).await.unwrap();
+        });
+
+        assert_eq!(rx.next().await.unwrap().unwrap(), Comm::Disconnected(1));
+        match rx.next().await.unwrap().unwrap() {
+            Comm::Message(msg) => assert_eq!(msg.content(), "Hi"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(rx.next().await.unwrap().unwrap(), Comm::Accepted);
+        sending.await.unwrap();
+        assert!(rx.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn partial_frames() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write(&Comm::AddFriend(5)).unwrap();
+        let data = writer.into_inner();
+
+        let (mut client, server) = io::duplex(1024);
+        let mut rx = FramedRead::new(server, TalkCodec::new());
+        tokio::spawn(async move {
+            for byte in data {
+                client.write_all(&[byte]).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        assert_eq!(rx.next().await.unwrap().unwrap(), Comm::AddFriend(5));
+    }
+
+    #[tokio::test]
+    async fn oversized_frame() {
+        let (mut client, server) = io::duplex(64);
+        let mut rx = FramedRead::new(server, TalkCodec::new());
+        let max_len = ServerLimits::default().frame_len();
+        let len = max_len as u32 + 1;
+        client.write_all(&len.to_le_bytes()).await.unwrap();
+
+        match rx.next().await.unwrap() {
+            Err(FrameError::TooLarge(l, max)) => {
+                assert_eq!(l, len as usize);
+                assert_eq!(max, max_len);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn compatible_with_frame_reader() {
+        let mut codec = TalkCodec::new();
+        let mut buffer = BytesMut::new();
+        codec.encode(&Comm::RemoveFriend(3), &mut buffer).unwrap();
+        codec.encode(Comm::Accepted, &mut buffer).unwrap();
+
+        let mut reader = FrameReader::new(&buffer[..]);
+        assert_eq!(reader.read::<Comm>().unwrap(), Some(Comm::RemoveFriend(3)));
+        assert_eq!(reader.read::<Comm>().unwrap(), Some(Comm::Accepted));
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let limits = ServerLimits {
+            max_frame_len: 8,
+            ..Default::default()
+        };
+        let mut codec = TalkCodec::with_limits(limits);
+        let mut buffer = BytesMut::new();
+        codec.encode(Comm::Accepted, &mut buffer).unwrap();
+        assert_eq!(&buffer[..], [1, 0, 0, 0, 3]);
+
+        match codec.encode(Comm::AddFriend(2), &mut buffer) {
+            Err(FrameError::TooLarge(len, max)) => assert_eq!((len, max), (9, 8)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
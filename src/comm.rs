@@ -1,27 +1,43 @@
 mod comm_error;
 
 use crate::{
+    auth::{AuthParams, AuthVerifier, Nonce, PasswordChange, Proof, NONCE_LEN, PROOF_LEN},
+    message::{time_from_millis, time_to_millis},
     serialize::{Serialize, SerializeError},
-    Message, User, UserID, MAX_PASS_BYTE_LEN,
+    Hello, Message, MessageId, PasswordError, PasswordPolicy, PublicKey, RequestId, SealedMessage,
+    SeqNum, ServerLimits, SessionToken, UserID, UserProfile, PUBLIC_KEY_LEN, SESSION_TOKEN_LEN,
+    USER_ID_SIZE,
 };
 pub use comm_error::CommError;
+use std::{convert::TryInto, time::SystemTime};
 
 /// Communication inteterface between `talk-client` and `talk-server`.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Comm {
     /// This message server will send to every newly connected and not logged client in case user
     /// want to create new account this will be new ID reserved for time of creating new account.
-    Connected(UserID),
+    /// Client can create account with this ID using `Register` until reservation expires. See
+    /// `IdReservation`.
+    Connected {
+        /// ID reserved for new account.
+        id: UserID,
+        /// Challenge that client must answer in `Login`. See `AuthVerifier`.
+        nonce: Nonce,
+    },
 
-    /// This message should be send by client every time it disconnects from server.
+    /// This message should be send by client every time it disconnects from server. Server sends
+    /// it before it closes connection with client. `Connection::close` sends it for both sides.
     Disconnected(UserID),
 
-    /// This must be send every time client want to authenticate with server.
+    /// This must be send every time client want to authenticate with server. Password is never
+    /// send, only proof computed with `login_proof` from password, parameters received in
+    /// `AuthParams` and nonce received in `Connected`.
     Login {
         /// This is `UserID`.
         id: UserID,
-        /// This is `User` password.
-        password: String,
+        /// Answer to server challenge.
+        proof: Proof,
     },
 
     /// Used every time when client or server should confirm operation without returning any data
@@ -32,220 +48,1439 @@ pub enum Comm {
     Rejected(CommError),
 
     /// This will be send to client after successfull authentication. Client should never send back
-    /// this to server. This will cause Rejected answer.
-    User(User),
+    /// this to server. This will cause Rejected answer. It contains only public `UserProfile`,
+    /// never `Account` data.
+    User(UserProfile),
 
     /// Client can use it to change password on server. Server will return Accepted on success or
-    /// CommError::InvalidPassword otherwise.
-    ChangePassword {
-        /// New `User` password.
-        new_password: String,
-        /// Current `User` password.
-        old_password: String,
-    },
+    /// CommError::InvalidPassword otherwise. Passwords are never send, only new verifier encrypted
+    /// with key derived from current password, see `PasswordChange::open`. Create it with
+    /// `Comm::change_password`, so new password is checked before verifier is created.
+    ChangePassword(PasswordChange),
 
     /// Every time clients want to send message they must use this. All messages are send to server
-    /// and stored there until reciver will log in. Then server will send messagess one by one
-    /// (starting from oldest), waiting every time for client to send `Comm::Accept` message. If
-    /// server or client wont `Comm::Accept` message then message wasn't recieved and will remain
-    /// at server (and should remain at client) to send another time.
+    /// and stored there until reciver will log in, waiting every time for server to send
+    /// `Comm::Ack` with message ID. If client wont get `Comm::Ack` then message wasn't recieved
+    /// and should remain at client to send another time with the same ID. Server must drop
+    /// messages with sender and ID it has already seen, but still acknowledge them. Server can
+    /// keep messages in `MessageQueue` and lease them to reciever as `Comm::Message` confirmed with
+    /// `Comm::Ack`, or in `Outbox` that delivers them with `Comm::Deliver` confirmed with
+    /// `Comm::AckUpTo`.
     Message(Message),
 
-    /// This is used when user is logged. Client should never send `User` struct to server or it
+    /// This is used when user is logged. Client should never send `Comm::User` to server or it
     /// will be recjeted.
     AddInvitation(UserID),
 
-    /// This is used when user is logged. Client should never send `User` struct to server or it
+    /// This is used when user is logged. Client should never send `Comm::User` to server or it
     /// will be recjeted.
     RemoveInvitation(UserID),
 
-    /// This is used when user is logged. Client should never send `User` struct to server or it
+    /// This is used when user is logged. Client should never send `Comm::User` to server or it
     /// will be recjeted.
     AddFriend(UserID),
 
-    /// This is used when user is logged. Client should never send `User` struct to server or it
+    /// This is used when user is logged. Client should never send `Comm::User` to server or it
     /// will be recjeted.
     RemoveFriend(UserID),
+
+    /// This must be the first message send by client after connecting. Server answers with its own
+    /// Hello followed by `Connected`, or with `Rejected(CommError::VersionMismatch)` when there is
+    /// no protocol version supported by both sides. Use `Hello::negotiate` to pick version.
+    Hello(Hello),
+
+    /// Server sends it right after its `Hello`. From now on both sides must use these limits for
+    /// serialization and validation instead of default ones.
+    Limits(ServerLimits),
+
+    /// Server sends it right after `User` following successful `Login` or `Resume`. Client can use
+    /// token to authenticate with `Resume` after reconnecting, until session expires.
+    Session {
+        /// Token that must be kept secret by client. It is never written by serde.
+        #[cfg_attr(feature = "serde", serde(skip))]
+        token: SessionToken,
+        /// Time after which server won't accept token anymore.
+        expires_at: SystemTime,
+    },
+
+    /// Client can send it instead of `Login` to authenticate with token received in `Session`.
+    /// Server answers like for `Login`, or with `Rejected(CommError::BadLoginData)` if token is
+    /// unknown or expired.
+    Resume {
+        /// Token received in `Session`. It is never written by serde.
+        #[cfg_attr(feature = "serde", serde(skip))]
+        token: SessionToken,
+    },
+
+    /// Client can use it to create new account with ID received in `Connected`. Server will return
+    /// `User` on success, `Rejected(CommError::IdTaken)` or `Rejected(CommError::IdExpired)` if ID
+    /// can't be used. Password is never send, so server can't check it. Create it with
+    /// `Comm::register`, so password is checked before verifier is created. Client must `Login`
+    /// after registration.
+    ///
+    /// # Security
+    /// Verifier is sent without encryption, so this must be sent only over encrypted transport,
+    /// e.g. TLS. Anybody who sees it can guess password offline and log in as the user once they
+    /// see any of its `Login`. See `AuthVerifier`.
+    Register {
+        /// ID received in `Connected`.
+        id: UserID,
+        /// Login verifier of new `Account` created by client with random salt.
+        verifier: AuthVerifier,
+        /// Name displayed to other users. It can't be longer than 255 bytes.
+        name: String,
+    },
+
+    /// Client sends its public key after login, so other users can send it `SealedMessage`.
+    /// Server will return Accepted and send key to everybody who asks with `RequestKey`.
+    PublishKey(PublicKey),
+
+    /// Client asks server for public key of user. Server will return `UserKey` or
+    /// `Rejected(CommError::InvalidUserId)` if user didn't publish any key.
+    RequestKey(UserID),
+
+    /// Public key of user, send by server as answer to `RequestKey`.
+    UserKey {
+        /// Owner of key.
+        id: UserID,
+        /// Public key published by owner.
+        key: PublicKey,
+    },
+
+    /// Message with end-to-end encrypted content. It is handled by server just like `Message`,
+    /// but server can't read its content.
+    SealedMessage(SealedMessage),
+
+    /// Confirms that message with given sender and ID was received. Server sends it to sender of
+    /// `Message` once message is stored, client sends it to server once message is delivered.
+    Ack {
+        /// Sender of confirmed message.
+        from: UserID,
+        /// ID of confirmed message.
+        id: MessageId,
+    },
+
+    /// Server sends stored messages with it, starting from oldest. Each conversation (sender and
+    /// reciever pair) has its own sequence numbers. Server doesn't wait for each message to be
+    /// confirmed, it can have window of messages in flight. See `Outbox` and `Inbox`.
+    Deliver {
+        /// Sequence number of message in conversation.
+        seq: SeqNum,
+        /// Delivered message.
+        message: Message,
+    },
+
+    /// Client confirms all messages from `from` with sequence number up to `seq`. Messages that
+    /// weren't confirmed remain at server and will be delivered again after reconnect.
+    AckUpTo {
+        /// Sender of confirmed messages.
+        from: UserID,
+        /// Sequence number of last confirmed message.
+        seq: SeqNum,
+    },
+
+    /// Client request wrapped with ID. Server handles `request` as if it was send alone and
+    /// answers with `Response` carrying the same ID, so client can send next request before
+    /// previous one is answered. See `PendingRequests`.
+    Request {
+        /// ID chosen by client.
+        id: RequestId,
+        /// Wrapped request, e.g. `AddFriend`. It can't be another `Request` or `Response`.
+        request: Box<Comm>,
+    },
+
+    /// Server answer to `Request` with the same ID, e.g. `Accepted` or `Rejected`.
+    Response {
+        /// ID of answered request.
+        id: RequestId,
+        /// Wrapped answer. It can't be another `Request` or `Response`.
+        response: Box<Comm>,
+    },
+
+    /// Either side can send it to check if peer is still alive. Peer must answer with `Pong`
+    /// containing the same nonce. See `Heartbeat`.
+    Ping(u64),
+
+    /// Answer to `Ping` with its nonce.
+    Pong(u64),
+
+    /// Client sends it when delivered message doesn't directly follow last received one, so some
+    /// messages were lost. It confirms messages up to `seq` like `AckUpTo` and server delivers
+    /// again all following messages. See `Inbox::receive`.
+    Resend {
+        /// Sender of lost messages.
+        from: UserID,
+        /// Sequence number of last received message.
+        seq: SeqNum,
+    },
+
+    /// The same as `Deliver`, but for end-to-end encrypted messages. It shares sequence numbers
+    /// with `Deliver` from the same sender.
+    DeliverSealed {
+        /// Sequence number of message in conversation.
+        seq: SeqNum,
+        /// Delivered message.
+        message: SealedMessage,
+    },
+
+    /// Client sends it before `Login` to get parameters it needs to compute proof for user.
+    /// Server answers with `AuthParams`.
+    RequestAuthParams(UserID),
+
+    /// Server answer to `RequestAuthParams` with salt and iterations of account. For unknown user
+    /// server sends `AuthParams::decoy`, so client can't find out which users exist.
+    AuthParams(AuthParams),
+
+    /// Server sends it after client connects, before first `Deliver` or `DeliverSealed` from
+    /// `from`, so both sides agree on sequence numbers even if one of them lost its state. See
+    /// `Outbox::start` and `Inbox::start`.
+    DeliveryStart {
+        /// Sender of messages.
+        from: UserID,
+        /// Random number that identifies `Outbox`. It changes when server loses its outbox.
+        epoch: u64,
+        /// Sequence number of last message that server knows was acknowledged.
+        seq: SeqNum,
+    },
+}
+
+impl Comm {
+    /// Creates `Register` with verifier of `password` if it follows `policy`. Use
+    /// `ServerLimits::password_policy` to get policy of server. Returns `PasswordError` otherwise.
+    pub fn register(
+        id: UserID,
+        password: &str,
+        name: String,
+        policy: &PasswordPolicy,
+    ) -> Result<Comm, PasswordError> {
+        policy.validate(password)?;
+        Ok(Comm::Register {
+            id,
+            verifier: AuthVerifier::new(password),
+            name,
+        })
+    }
+
+    /// Creates `ChangePassword` for user `id` if `new_password` follows `policy`. `params` are
+    /// current parameters received in `AuthParams` and `nonce` is the one received in
+    /// `Connected`. Use `ServerLimits::password_policy` to get policy of server. Returns
+    /// `PasswordError` otherwise.
+    pub fn change_password(
+        id: UserID,
+        new_password: &str,
+        old_password: &str,
+        params: &AuthParams,
+        nonce: &Nonce,
+        policy: &PasswordPolicy,
+    ) -> Result<Comm, PasswordError> {
+        policy.validate(new_password)?;
+        Ok(Comm::ChangePassword(PasswordChange::new(
+            id,
+            old_password,
+            params,
+            nonce,
+            new_password,
+        )))
+    }
 }
 
 impl Serialize for Comm {
-    type Item = Comm;
+    fn encoded_len(&self) -> usize {
+        let payload_len = match self {
+            Comm::Connected { .. } => USER_ID_SIZE + NONCE_LEN,
+            Comm::Login { .. } => USER_ID_SIZE + PROOF_LEN,
+            Comm::Accepted => 0,
+            Comm::Rejected(err) => err.encoded_len(),
+            Comm::User(user) => user.encoded_len(),
+            Comm::ChangePassword(_) => PasswordChange::BYTE_LEN,
+            Comm::Message(msg) => msg.encoded_len(),
+            Comm::Disconnected(_)
+            | Comm::AddInvitation(_)
+            | Comm::RemoveInvitation(_)
+            | Comm::AddFriend(_)
+            | Comm::RemoveFriend(_)
+            | Comm::RequestKey(_)
+            | Comm::RequestAuthParams(_)
+            | Comm::Ping(_)
+            | Comm::Pong(_) => 8,
+            Comm::Hello(hello) => hello.encoded_len(),
+            Comm::AuthParams(params) => params.encoded_len(),
+            Comm::Limits(limits) => limits.encoded_len(),
+            Comm::Session { .. } => SESSION_TOKEN_LEN + 8,
+            Comm::Resume { .. } => SESSION_TOKEN_LEN,
+            Comm::Register { name, .. } => USER_ID_SIZE + AuthVerifier::BYTE_LEN + 1 + name.len(),
+            Comm::PublishKey(_) => PUBLIC_KEY_LEN,
+            Comm::UserKey { .. } => USER_ID_SIZE + PUBLIC_KEY_LEN,
+            Comm::SealedMessage(msg) => msg.encoded_len(),
+            Comm::Deliver { message, .. } => 8 + message.encoded_len(),
+            Comm::Ack { .. } | Comm::AckUpTo { .. } | Comm::Resend { .. } => USER_ID_SIZE + 8,
+            Comm::DeliveryStart { .. } => USER_ID_SIZE + 8 + 8,
+            Comm::DeliverSealed { message, .. } => 8 + message.encoded_len(),
+            Comm::Request { request: comm, .. } | Comm::Response { response: comm, .. } => {
+                4 + comm.encoded_len()
+            }
+        };
+        1 + payload_len
+    }
+
+    /// Writes Comm to `buffer`. Returns number of bytes written on success or `SerializeError`
+    /// otherwise.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        let (tag, buffer) = buffer
+            .split_first_mut()
+            .ok_or(SerializeError::NotEnoughData)?;
 
-    /// Writes Comm to `buffer`. Returns `()` on success or `SerializeError` otherwise.
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), SerializeError> {
-        // TODO: Add buffer size checks to each condition so we won't panic here but return proper
-        // SerializeError. For now it will be just MVP that panic when we try to write too much.
-        match self {
-            Comm::Connected(id) => {
-                buffer[0] = 0;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+        let len = match self {
+            Comm::Connected { id, nonce } => {
+                *tag = 0;
+                let index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], nonce)?
             }
 
             Comm::Disconnected(id) => {
-                buffer[0] = 1;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+                *tag = 1;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
             }
 
-            Comm::Login { id, password } => {
-                buffer[0] = 2;
-                let mut index = 1;
-                index += crate::write_bytes_to_buffer(&mut buffer[index..], &id.to_ne_bytes());
-                crate::write_bytes_to_buffer(&mut buffer[index..], password.as_bytes());
+            Comm::Login { id, proof } => {
+                *tag = 2;
+                let index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], proof)?
             }
 
-            Comm::Accepted => buffer[0] = 3,
+            Comm::Accepted => {
+                *tag = 3;
+                0
+            }
 
             Comm::Rejected(err) => {
-                buffer[0] = 4;
-                err.serialize(&mut buffer[1..])?
+                *tag = 4;
+                err.serialize(buffer)?
             }
 
             Comm::User(user) => {
-                buffer[0] = 5;
-                user.serialize(&mut buffer[1..])?
-            }
-
-            Comm::ChangePassword {
-                new_password,
-                old_password,
-            } => {
-                buffer[0] = 6;
-                let np = new_password.as_bytes();
-                let op = old_password.as_bytes();
-                let index = 1 + crate::MAX_PASS_BYTE_LEN;
-
-                crate::write_bytes_to_buffer(&mut buffer[1..index], np);
-                // Each password must have reserved exactly the same number of bytes.
-                crate::write_bytes_to_buffer(
-                    &mut buffer[index..index + crate::MAX_PASS_BYTE_LEN],
-                    op,
-                );
+                *tag = 5;
+                user.serialize(buffer)?
+            }
+
+            Comm::ChangePassword(change) => {
+                *tag = 6;
+                change.serialize(buffer)?
             }
 
             Comm::Message(msg) => {
-                buffer[0] = 7;
-                msg.serialize(&mut buffer[1..])?
+                *tag = 7;
+                msg.serialize(buffer)?
             }
 
             Comm::AddInvitation(id) => {
-                buffer[0] = 8;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+                *tag = 8;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
             }
 
             Comm::RemoveInvitation(id) => {
-                buffer[0] = 9;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+                *tag = 9;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
             }
 
             Comm::AddFriend(id) => {
-                buffer[0] = 10;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+                *tag = 10;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
             }
 
             Comm::RemoveFriend(id) => {
-                buffer[0] = 11;
-                crate::write_bytes_to_buffer(&mut buffer[1..], &id.to_ne_bytes());
+                *tag = 11;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
+            }
+
+            Comm::Hello(hello) => {
+                *tag = 12;
+                hello.serialize(buffer)?
+            }
+
+            Comm::Limits(limits) => {
+                *tag = 13;
+                limits.serialize(buffer)?
+            }
+
+            Comm::Session { token, expires_at } => {
+                *tag = 14;
+                let index = crate::write_bytes_to_buffer(buffer, token.as_bytes())?;
+                index
+                    + crate::write_bytes_to_buffer(
+                        &mut buffer[index..],
+                        &time_to_millis(expires_at).to_le_bytes(),
+                    )?
+            }
+
+            Comm::Resume { token } => {
+                *tag = 15;
+                crate::write_bytes_to_buffer(buffer, token.as_bytes())?
+            }
+
+            Comm::Register { id, verifier, name } => {
+                *tag = 16;
+                let name = name.as_bytes();
+                // Name longer than u8 can't be described by length field.
+                if name.len() > u8::MAX as usize {
+                    return Err(SerializeError::StringTooLong {
+                        type_name: "Comm".into(),
+                        offset: 1 + USER_ID_SIZE + AuthVerifier::BYTE_LEN,
+                        len: name.len(),
+                        max: u8::MAX as usize,
+                    });
+                }
+                let mut index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index += verifier.serialize(&mut buffer[index..])?;
+                index += crate::write_bytes_to_buffer(&mut buffer[index..], &[name.len() as u8])?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], name)?
+            }
+
+            Comm::PublishKey(key) => {
+                *tag = 17;
+                crate::write_bytes_to_buffer(buffer, key)?
+            }
+
+            Comm::RequestKey(id) => {
+                *tag = 18;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
+            }
+
+            Comm::UserKey { id, key } => {
+                *tag = 19;
+                let index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], key)?
             }
-        }
 
-        Ok(())
+            Comm::SealedMessage(msg) => {
+                *tag = 20;
+                msg.serialize(buffer)?
+            }
+
+            Comm::Ack { from, id } => {
+                *tag = 21;
+                let index = crate::write_bytes_to_buffer(buffer, &from.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], &id.to_le_bytes())?
+            }
+
+            Comm::Deliver { seq, message } => {
+                *tag = 22;
+                let index = crate::write_bytes_to_buffer(buffer, &seq.to_le_bytes())?;
+                index + message.serialize(&mut buffer[index..])?
+            }
+
+            Comm::AckUpTo { from, seq } => {
+                *tag = 23;
+                let index = crate::write_bytes_to_buffer(buffer, &from.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], &seq.to_le_bytes())?
+            }
+
+            Comm::Request { id, request } => {
+                *tag = 24;
+                let index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index + request.serialize(&mut buffer[index..])?
+            }
+
+            Comm::Response { id, response } => {
+                *tag = 25;
+                let index = crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?;
+                index + response.serialize(&mut buffer[index..])?
+            }
+
+            Comm::Ping(nonce) => {
+                *tag = 26;
+                crate::write_bytes_to_buffer(buffer, &nonce.to_le_bytes())?
+            }
+
+            Comm::Pong(nonce) => {
+                *tag = 27;
+                crate::write_bytes_to_buffer(buffer, &nonce.to_le_bytes())?
+            }
+
+            Comm::Resend { from, seq } => {
+                *tag = 28;
+                let index = crate::write_bytes_to_buffer(buffer, &from.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], &seq.to_le_bytes())?
+            }
+
+            Comm::DeliverSealed { seq, message } => {
+                *tag = 29;
+                let index = crate::write_bytes_to_buffer(buffer, &seq.to_le_bytes())?;
+                index + message.serialize(&mut buffer[index..])?
+            }
+
+            Comm::RequestAuthParams(id) => {
+                *tag = 30;
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
+            }
+
+            Comm::AuthParams(params) => {
+                *tag = 31;
+                params.serialize(buffer)?
+            }
+
+            Comm::DeliveryStart { from, epoch, seq } => {
+                *tag = 32;
+                let mut index = crate::write_bytes_to_buffer(buffer, &from.to_le_bytes())?;
+                index += crate::write_bytes_to_buffer(&mut buffer[index..], &epoch.to_le_bytes())?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], &seq.to_le_bytes())?
+            }
+        };
+
+        Ok(1 + len)
     }
 
-    /// Reads Comm from `buffer`. Returns `Self` on success or `SerializeError` otherwise.
-    fn deserialize(buffer: &[u8]) -> Result<Self::Item, SerializeError> {
-        match buffer[0] {
+    /// Reads Comm from `buffer`. Returns `Self` and number of bytes read on success or
+    /// `SerializeError` otherwise.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        let (tag, buffer) = buffer.split_first().ok_or(SerializeError::NotEnoughData)?;
+        let id = || crate::parse_id_from_bytes(buffer);
+
+        let (comm, len) = match tag {
             // Comm::Connected
             0 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..9]);
-                Ok(Comm::Connected(id))
+                let nonce = buffer
+                    .get(USER_ID_SIZE..USER_ID_SIZE + NONCE_LEN)
+                    .ok_or(SerializeError::NotEnoughData)?;
+                let comm = Comm::Connected {
+                    id: id()?,
+                    nonce: nonce.try_into().unwrap(),
+                };
+                (comm, USER_ID_SIZE + NONCE_LEN)
             }
 
             // Comm::Disconnnected
-            1 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..9]);
-                Ok(Comm::Disconnected(id))
-            }
+            1 => (Comm::Disconnected(id()?), USER_ID_SIZE),
 
             // Comm::Login
             2 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..9]);
-                let password =
-                    crate::parse_string_from_bytes(&buffer[9..9 + MAX_PASS_BYTE_LEN]).to_string();
-                Ok(Comm::Login { id, password })
+                let proof = buffer
+                    .get(USER_ID_SIZE..USER_ID_SIZE + PROOF_LEN)
+                    .ok_or(SerializeError::NotEnoughData)?;
+                let comm = Comm::Login {
+                    id: id()?,
+                    proof: proof.try_into().unwrap(),
+                };
+                (comm, USER_ID_SIZE + PROOF_LEN)
             }
 
             // Comm::Accepted
-            3 => Ok(Comm::Accepted),
+            3 => (Comm::Accepted, 0),
 
             // Comm::Rejected
-            4 => Ok(Comm::Rejected(
-                CommError::deserialize(&buffer[1..]).unwrap(),
-            )),
+            4 => {
+                let (err, len) = CommError::deserialize(buffer)?;
+                (Comm::Rejected(err), len)
+            }
 
             // Comm::User
-            5 => Ok(Comm::User(User::deserialize(&buffer[1..]).unwrap())),
+            5 => {
+                let (user, len) = UserProfile::deserialize(buffer)?;
+                (Comm::User(user), len)
+            }
 
             // Comm::ChangePassword
             6 => {
-                let index = 1 + MAX_PASS_BYTE_LEN;
-                let new_password = crate::parse_string_from_bytes(&buffer[1..index]).to_string();
-                let old_password =
-                    crate::parse_string_from_bytes(&buffer[index..index + MAX_PASS_BYTE_LEN])
-                        .to_string();
-                Ok(Comm::ChangePassword {
-                    new_password,
-                    old_password,
-                })
+                let (change, len) = PasswordChange::deserialize(buffer)?;
+                (Comm::ChangePassword(change), len)
             }
 
             // Comm::Message
-            7 => unimplemented!(),
+            7 => {
+                let (msg, len) = Message::deserialize(buffer)?;
+                (Comm::Message(msg), len)
+            }
 
             // Comm::AddInvitation
-            8 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..]);
-                Ok(Comm::AddInvitation(id))
-            }
+            8 => (Comm::AddInvitation(id()?), USER_ID_SIZE),
 
             // Comm::RemoveInvitation
-            9 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..]);
-                Ok(Comm::RemoveInvitation(id))
-            }
+            9 => (Comm::RemoveInvitation(id()?), USER_ID_SIZE),
 
             // Comm::AddFriend
-            10 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..]);
-                Ok(Comm::AddFriend(id))
-            }
+            10 => (Comm::AddFriend(id()?), USER_ID_SIZE),
 
             // Comm::RemoveFriend
-            11 => {
-                let id = crate::parse_id_from_bytes(&buffer[1..]);
-                Ok(Comm::RemoveFriend(id))
+            11 => (Comm::RemoveFriend(id()?), USER_ID_SIZE),
+
+            // Comm::Hello
+            12 => {
+                let (hello, len) = Hello::deserialize(buffer)?;
+                (Comm::Hello(hello), len)
+            }
+
+            // Comm::Limits
+            13 => {
+                let (limits, len) = ServerLimits::deserialize(buffer)?;
+                (Comm::Limits(limits), len)
+            }
+
+            // Comm::Session
+            14 => {
+                let token = parse_token(buffer)?;
+                let millis = buffer
+                    .get(SESSION_TOKEN_LEN..SESSION_TOKEN_LEN + 8)
+                    .ok_or(SerializeError::NotEnoughData)?;
+                let comm = Comm::Session {
+                    token,
+                    expires_at: time_from_millis(u64::from_le_bytes(millis.try_into().unwrap())),
+                };
+                (comm, SESSION_TOKEN_LEN + 8)
+            }
+
+            // Comm::Resume
+            15 => {
+                let comm = Comm::Resume {
+                    token: parse_token(buffer)?,
+                };
+                (comm, SESSION_TOKEN_LEN)
+            }
+
+            // Comm::Register
+            16 => {
+                let id = id()?;
+                let (verifier, len) = AuthVerifier::deserialize(&buffer[USER_ID_SIZE..])?;
+                let mut index = USER_ID_SIZE + len;
+                let name_len = *buffer.get(index).ok_or(SerializeError::NotEnoughData)? as usize;
+                index += 1;
+                let name = buffer
+                    .get(index..index + name_len)
+                    .ok_or(SerializeError::NotEnoughData)?;
+                let name = crate::parse_utf8(name, "Comm", 1 + index)?.to_string();
+                (Comm::Register { id, verifier, name }, index + name_len)
+            }
+
+            // Comm::PublishKey
+            17 => (Comm::PublishKey(parse_key(buffer)?), PUBLIC_KEY_LEN),
+
+            // Comm::RequestKey
+            18 => (Comm::RequestKey(id()?), USER_ID_SIZE),
+
+            // Comm::UserKey
+            19 => {
+                let comm = Comm::UserKey {
+                    id: id()?,
+                    key: parse_key(buffer.get(USER_ID_SIZE..).unwrap_or_default())?,
+                };
+                (comm, USER_ID_SIZE + PUBLIC_KEY_LEN)
+            }
+
+            // Comm::SealedMessage
+            20 => {
+                let (msg, len) = SealedMessage::deserialize(buffer)?;
+                (Comm::SealedMessage(msg), len)
+            }
+
+            // Comm::Ack
+            21 => {
+                let comm = Comm::Ack {
+                    from: id()?,
+                    id: crate::parse_id_from_bytes(buffer.get(USER_ID_SIZE..).unwrap_or_default())?,
+                };
+                (comm, USER_ID_SIZE + 8)
+            }
+
+            // Comm::Deliver
+            22 => {
+                let seq = id()?;
+                let (message, len) = Message::deserialize(&buffer[8..])?;
+                (Comm::Deliver { seq, message }, 8 + len)
+            }
+
+            // Comm::AckUpTo
+            23 => {
+                let comm = Comm::AckUpTo {
+                    from: id()?,
+                    seq: crate::parse_id_from_bytes(
+                        buffer.get(USER_ID_SIZE..).unwrap_or_default(),
+                    )?,
+                };
+                (comm, USER_ID_SIZE + 8)
+            }
+
+            // Comm::Request
+            24 => {
+                let id = parse_request_id(buffer)?;
+                let (request, len) = deserialize_wrapped(&buffer[4..])?;
+                let comm = Comm::Request {
+                    id,
+                    request: Box::new(request),
+                };
+                (comm, 4 + len)
+            }
+
+            // Comm::Response
+            25 => {
+                let id = parse_request_id(buffer)?;
+                let (response, len) = deserialize_wrapped(&buffer[4..])?;
+                let comm = Comm::Response {
+                    id,
+                    response: Box::new(response),
+                };
+                (comm, 4 + len)
+            }
+
+            // Comm::Ping
+            26 => (Comm::Ping(id()?), 8),
+
+            // Comm::Pong
+            27 => (Comm::Pong(id()?), 8),
+
+            // Comm::Resend
+            28 => {
+                let comm = Comm::Resend {
+                    from: id()?,
+                    seq: crate::parse_id_from_bytes(
+                        buffer.get(USER_ID_SIZE..).unwrap_or_default(),
+                    )?,
+                };
+                (comm, USER_ID_SIZE + 8)
+            }
+
+            // Comm::DeliverSealed
+            29 => {
+                let seq = id()?;
+                let (message, len) = SealedMessage::deserialize(&buffer[8..])?;
+                (Comm::DeliverSealed { seq, message }, 8 + len)
+            }
+
+            // Comm::RequestAuthParams
+            30 => (Comm::RequestAuthParams(id()?), USER_ID_SIZE),
+
+            // Comm::AuthParams
+            31 => {
+                let (params, len) = AuthParams::deserialize(buffer)?;
+                (Comm::AuthParams(params), len)
+            }
+
+            // Comm::DeliveryStart
+            32 => {
+                let field = |index: usize| {
+                    crate::parse_id_from_bytes(buffer.get(index..).unwrap_or_default())
+                };
+                let comm = Comm::DeliveryStart {
+                    from: id()?,
+                    epoch: field(USER_ID_SIZE)?,
+                    seq: field(USER_ID_SIZE + 8)?,
+                };
+                (comm, USER_ID_SIZE + 8 + 8)
             }
 
             // Unknown Comm signature
-            sig => Err(SerializeError::UnknownSignature(sig)),
-        }
+            &sig => return Err(SerializeError::UnknownSignature(sig)),
+        };
+
+        Ok((comm, 1 + len))
     }
 }
 
+/// Reads Comm wrapped by `Request` or `Response`. It can't be another `Request` or `Response`, so
+/// peer can't exhaust stack with deeply nested frame.
+fn deserialize_wrapped(buffer: &[u8]) -> Result<(Comm, usize), SerializeError> {
+    match buffer.first() {
+        Some(&sig @ (24 | 25)) => Err(SerializeError::UnknownSignature(sig)),
+        _ => Comm::deserialize(buffer),
+    }
+}
+
+/// Returns `SessionToken` from the beginning of `buffer`.
+fn parse_token(buffer: &[u8]) -> Result<SessionToken, SerializeError> {
+    let token = buffer
+        .get(..SESSION_TOKEN_LEN)
+        .ok_or(SerializeError::NotEnoughData)?;
+    Ok(SessionToken::from_bytes(token.try_into().unwrap()))
+}
+
+/// Returns `RequestId` from the beginning of `buffer`.
+fn parse_request_id(buffer: &[u8]) -> Result<RequestId, SerializeError> {
+    let id = buffer.get(..4).ok_or(SerializeError::NotEnoughData)?;
+    Ok(RequestId::from_le_bytes(id.try_into().unwrap()))
+}
+
+/// Returns `PublicKey` from the beginning of `buffer`.
+fn parse_key(buffer: &[u8]) -> Result<PublicKey, SerializeError> {
+    let key = buffer
+        .get(..PUBLIC_KEY_LEN)
+        .ok_or(SerializeError::NotEnoughData)?;
+    Ok(key.try_into().unwrap())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{AUTH_ITERATIONS, AUTH_SALT_LEN, MAX_PASS_BYTE_LEN};
+
+    /// Returns verifier with fixed salt and key, so tests don't have to derive it.
+    fn verifier() -> AuthVerifier {
+        AuthVerifier {
+            params: AuthParams {
+                salt: [9u8; AUTH_SALT_LEN],
+                iterations: AUTH_ITERATIONS,
+            },
+            stored_key: [8u8; PROOF_LEN],
+        }
+    }
 
     #[test]
     fn comm_connect() {
-        let buffer = [0u8, 1, 0, 0, 0, 0, 0, 0, 0];
-        assert_eq!(Comm::deserialize(&buffer).unwrap(), Comm::Connected(1));
-        assert_ne!(Comm::deserialize(&buffer).unwrap(), Comm::Connected(2));
+        let mut buffer = vec![0u8, 1, 0, 0, 0, 0, 0, 0, 0];
+        buffer.extend_from_slice(&[7u8; NONCE_LEN]);
+        let connected = |id| Comm::Connected {
+            id,
+            nonce: [7u8; NONCE_LEN],
+        };
+        assert_eq!(Comm::deserialize(&buffer).unwrap().0, connected(1));
+        assert_ne!(Comm::deserialize(&buffer).unwrap().0, connected(2));
     }
 
     #[test]
     fn comm_disconnected() {
         let buffer = [1u8, 1, 0, 0, 0, 0, 0, 0, 0];
-        assert_eq!(Comm::deserialize(&buffer).unwrap(), Comm::Disconnected(1));
-        assert_ne!(Comm::deserialize(&buffer).unwrap(), Comm::Disconnected(2));
+        assert_eq!(Comm::deserialize(&buffer).unwrap().0, Comm::Disconnected(1));
+        assert_ne!(Comm::deserialize(&buffer).unwrap().0, Comm::Disconnected(2));
+    }
+
+    /// Every variant with number of bytes it needs on the wire.
+    fn all_variants() -> Vec<(Comm, usize)> {
+        let mut user = UserProfile::new(1);
+        user.add_friend(2);
+        user.add_invitation(3);
+
+        vec![
+            (
+                Comm::Connected {
+                    id: 1,
+                    nonce: [1u8; NONCE_LEN],
+                },
+                1 + USER_ID_SIZE + NONCE_LEN,
+            ),
+            (Comm::Disconnected(1), 9),
+            (
+                Comm::Login {
+                    id: 1,
+                    proof: [2u8; PROOF_LEN],
+                },
+                1 + USER_ID_SIZE + PROOF_LEN,
+            ),
+            (Comm::Accepted, 1),
+            (Comm::Rejected(CommError::InvalidOperation), 2),
+            (Comm::Rejected(CommError::Custom("Bye".to_string())), 6),
+            (Comm::User(user), 1 + USER_ID_SIZE + 3 + 2 * USER_ID_SIZE),
+            (
+                Comm::ChangePassword(PasswordChange {
+                    params: *verifier().params(),
+                    encrypted_key: [2u8; PROOF_LEN],
+                    proof: [3u8; PROOF_LEN],
+                }),
+                1 + PasswordChange::BYTE_LEN,
+            ),
+            (
+                Comm::Message(Message::new("Hi".to_string(), 1, 2)),
+                1 + 2 * USER_ID_SIZE + 8 + 8 + 2 + 2,
+            ),
+            (Comm::AddInvitation(1), 9),
+            (Comm::RemoveInvitation(1), 9),
+            (Comm::AddFriend(1), 9),
+            (Comm::RemoveFriend(1), 9),
+            (Comm::Hello(Hello::new(1)), 9),
+            (Comm::Limits(ServerLimits::default()), 17),
+            (
+                Comm::Session {
+                    token: SessionToken::from_bytes([3u8; SESSION_TOKEN_LEN]),
+                    expires_at: time_from_millis(1_600_000_000_000),
+                },
+                1 + SESSION_TOKEN_LEN + 8,
+            ),
+            (
+                Comm::Resume {
+                    token: SessionToken::from_bytes([4u8; SESSION_TOKEN_LEN]),
+                },
+                1 + SESSION_TOKEN_LEN,
+            ),
+            (
+                Comm::Register {
+                    id: 1,
+                    verifier: verifier(),
+                    name: "Al".to_string(),
+                },
+                1 + USER_ID_SIZE + AuthVerifier::BYTE_LEN + 1 + 2,
+            ),
+            (Comm::PublishKey([5u8; PUBLIC_KEY_LEN]), 1 + PUBLIC_KEY_LEN),
+            (
+                Comm::SealedMessage(SealedMessage {
+                    from: 1,
+                    to: 2,
+                    id: 3,
+                    time: time_from_millis(1_600_000_000_000),
+                    nonce: [7u8; crate::SEALED_NONCE_LEN],
+                    ciphertext: vec![8u8; 18],
+                }),
+                1 + 2 * USER_ID_SIZE + 8 + 8 + crate::SEALED_NONCE_LEN + 2 + 18,
+            ),
+            (Comm::RequestKey(1), 9),
+            (Comm::Ack { from: 1, id: 2 }, 1 + USER_ID_SIZE + 8),
+            (Comm::Ping(1), 9),
+            (Comm::Pong(1), 9),
+            (
+                Comm::Deliver {
+                    seq: 1,
+                    message: Message::new("Hi".to_string(), 1, 2),
+                },
+                1 + 8 + 2 * USER_ID_SIZE + 8 + 8 + 2 + 2,
+            ),
+            (Comm::AckUpTo { from: 1, seq: 2 }, 1 + USER_ID_SIZE + 8),
+            (Comm::Resend { from: 1, seq: 2 }, 1 + USER_ID_SIZE + 8),
+            (
+                Comm::DeliverSealed {
+                    seq: 1,
+                    message: SealedMessage {
+                        from: 1,
+                        to: 2,
+                        id: 3,
+                        time: time_from_millis(1_600_000_000_000),
+                        nonce: [7u8; crate::SEALED_NONCE_LEN],
+                        ciphertext: vec![8u8; 2],
+                    },
+                },
+                1 + 8 + 2 * USER_ID_SIZE + 8 + 8 + crate::SEALED_NONCE_LEN + 2 + 2,
+            ),
+            (
+                Comm::Request {
+                    id: 1,
+                    request: Box::new(Comm::AddFriend(2)),
+                },
+                1 + 4 + 9,
+            ),
+            (
+                Comm::Response {
+                    id: 1,
+                    response: Box::new(Comm::Rejected(CommError::InvalidUserId)),
+                },
+                1 + 4 + 2,
+            ),
+            (
+                Comm::UserKey {
+                    id: 1,
+                    key: [6u8; PUBLIC_KEY_LEN],
+                },
+                1 + USER_ID_SIZE + PUBLIC_KEY_LEN,
+            ),
+            (Comm::RequestAuthParams(1), 9),
+            (
+                Comm::AuthParams(AuthParams {
+                    salt: [9u8; crate::AUTH_SALT_LEN],
+                    iterations: AUTH_ITERATIONS,
+                }),
+                1 + crate::AUTH_SALT_LEN + 4,
+            ),
+            (
+                Comm::DeliveryStart {
+                    from: 1,
+                    epoch: 2,
+                    seq: 3,
+                },
+                1 + USER_ID_SIZE + 8 + 8,
+            ),
+        ]
+    }
+
+    #[test]
+    fn serialize_to_short_buffer() {
+        for (comm, len) in all_variants() {
+            for short in 0..len {
+                let mut buffer = vec![0u8; short];
+                assert_eq!(
+                    comm.serialize(&mut buffer),
+                    Err(SerializeError::NotEnoughData),
+                    "{:?} into {} bytes",
+                    comm,
+                    short
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn deserialize_truncated() {
+        for (comm, len) in all_variants() {
+            let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+            comm.serialize(&mut buffer).unwrap();
+
+            for short in 0..len {
+                assert_eq!(
+                    Comm::deserialize(&buffer[..short]),
+                    Err(SerializeError::NotEnoughData),
+                    "{:?} from {} bytes",
+                    comm,
+                    short
+                );
+            }
+            assert!(Comm::deserialize(&buffer[..len]).is_ok(), "{:?}", comm);
+        }
+    }
+
+    #[test]
+    fn encoded_len() {
+        for (comm, len) in all_variants() {
+            assert_eq!(comm.encoded_len(), len, "{:?}", comm);
+            let mut buffer = [0xFFu8; crate::NET_BUFF_SIZE];
+            assert_eq!(comm.serialize(&mut buffer), Ok(len), "{:?}", comm);
+            assert_eq!(Comm::deserialize(&buffer).unwrap().1, len, "{:?}", comm);
+            assert_eq!(comm.serialize_to_vec().unwrap(), &buffer[..len]);
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut bytes = Comm::AddFriend(2).serialize_to_vec().unwrap();
+        assert_eq!(Comm::deserialize_exact(&bytes), Ok(Comm::AddFriend(2)));
+        bytes.push(0);
+        assert_eq!(
+            Comm::deserialize_exact(&bytes),
+            Err(SerializeError::TrailingData {
+                type_name: "Comm".into(),
+                offset: 1 + USER_ID_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn send_and_recive() {
+        for (comm, _) in all_variants() {
+            let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+            comm.serialize(&mut buffer).unwrap();
+            assert_eq!(Comm::deserialize(&buffer).unwrap().0, comm);
+        }
+    }
+
+    #[test]
+    fn message() {
+        let message = Message::new("Hello World".to_string(), 1, 2);
+        let time = *message.time();
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        Comm::Message(message).serialize(&mut buffer).unwrap();
+
+        match Comm::deserialize(&buffer).unwrap().0 {
+            Comm::Message(received) => {
+                assert_eq!(received.content(), "Hello World");
+                assert_eq!(received.from(), &1);
+                assert_eq!(received.to(), &2);
+                assert_eq!(received.time(), &time);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    /// Checks that `comm` is written exactly as `bytes`.
+    fn assert_golden(comm: Comm, bytes: &[u8]) {
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        comm.serialize(&mut buffer).unwrap();
+        assert_eq!(&buffer[..bytes.len()], bytes, "{:?}", comm);
+        assert!(buffer[bytes.len()..].iter().all(|&b| b == 0), "{:?}", comm);
+        assert_eq!(Comm::deserialize(bytes).unwrap().0, comm);
+    }
+
+    #[test]
+    fn golden_ids() {
+        let id: UserID = 0x0102_0304_0506_0708;
+        let le = [8, 7, 6, 5, 4, 3, 2, 1];
+        for (comm, tag) in [
+            (Comm::Disconnected(id), 1u8),
+            (Comm::AddInvitation(id), 8),
+            (Comm::RemoveInvitation(id), 9),
+            (Comm::AddFriend(id), 10),
+            (Comm::RemoveFriend(id), 11),
+            (Comm::RequestKey(id), 18),
+            (Comm::Ping(id), 26),
+            (Comm::RequestAuthParams(id), 30),
+            (Comm::Pong(id), 27),
+        ] {
+            let mut bytes = vec![tag];
+            bytes.extend_from_slice(&le);
+            assert_golden(comm, &bytes);
+        }
+    }
+
+    #[test]
+    fn golden_connected() {
+        let nonce: Nonce = std::array::from_fn(|i| i as u8);
+        let mut bytes = vec![0, 0x34, 0x12, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&nonce);
+        assert_golden(Comm::Connected { id: 0x1234, nonce }, &bytes);
+    }
+
+    #[test]
+    fn golden_login() {
+        let proof: Proof = std::array::from_fn(|i| 0xFF - i as u8);
+        let mut bytes = vec![2, 0x34, 0x12, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&proof);
+        assert_golden(Comm::Login { id: 0x1234, proof }, &bytes);
+    }
+
+    #[test]
+    fn golden_accepted_and_rejected() {
+        assert_golden(Comm::Accepted, &[3]);
+        assert_golden(Comm::Rejected(CommError::BadLoginData), &[4, 0]);
+        assert_golden(Comm::Rejected(CommError::InvalidUserId), &[4, 1]);
+        assert_golden(Comm::Rejected(CommError::InvalidPassword), &[4, 2]);
+        assert_golden(Comm::Rejected(CommError::InvalidOperation), &[4, 3]);
+        assert_golden(Comm::Rejected(CommError::Unknown), &[4, 4]);
+        assert_golden(Comm::Rejected(CommError::VersionMismatch), &[4, 5]);
+        assert_golden(
+            Comm::Rejected(CommError::UserOffline(0x0102)),
+            &[4, 12, 0x02, 0x01, 0, 0, 0, 0, 0, 0],
+        );
+    }
+
+    #[test]
+    fn golden_user() {
+        let mut user = UserProfile::new(0x0100);
+        user.set_name("Al".to_string());
+        user.add_friend(2);
+        user.add_invitation(0x0300);
+
+        let mut bytes = vec![5, 0, 1, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&[2, b'A', b'l']);
+        bytes.extend_from_slice(&[1, 1]);
+        bytes.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 3, 0, 0, 0, 0, 0, 0]);
+        assert_golden(Comm::User(user), &bytes);
+    }
+
+    #[test]
+    fn golden_change_password() {
+        let proof: Proof = std::array::from_fn(|i| i as u8);
+        let mut bytes = vec![6];
+        bytes.extend(verifier().params().serialize_to_vec().unwrap());
+        bytes.extend_from_slice(&[2u8; PROOF_LEN]);
+        bytes.extend_from_slice(&proof);
+        assert_golden(
+            Comm::ChangePassword(PasswordChange {
+                params: *verifier().params(),
+                encrypted_key: [2u8; PROOF_LEN],
+                proof,
+            }),
+            &bytes,
+        );
+    }
+
+    #[test]
+    fn golden_message() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(0x0102_0304_0506);
+        let message = Message::with_id(0x0A0B, "Hi".to_string(), 1, 0x0200, time);
+
+        let mut bytes = vec![7];
+        bytes.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0x0B, 0x0A, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[6, 5, 4, 3, 2, 1, 0, 0]);
+        bytes.extend_from_slice(&[2, 0, b'H', b'i']);
+        assert_golden(Comm::Message(message), &bytes);
+    }
+
+    #[test]
+    fn golden_deliver_and_ack() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(0x0102_0304_0506);
+        let message = Message::with_id(0x0A0B, "Hi".to_string(), 1, 0x0200, time);
+
+        let mut bytes = vec![22, 5, 0, 0, 0, 0, 0, 0, 0];
+        let mut message_bytes = [0u8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut message_bytes).unwrap();
+        bytes.extend_from_slice(&message_bytes[..2 * USER_ID_SIZE + 8 + 8 + 2 + 2]);
+        assert_golden(Comm::Deliver { seq: 5, message }, &bytes);
+
+        assert_golden(
+            Comm::AckUpTo {
+                from: 0x0102,
+                seq: 0x0304,
+            },
+            &[23, 2, 1, 0, 0, 0, 0, 0, 0, 4, 3, 0, 0, 0, 0, 0, 0],
+        );
+        assert_golden(
+            Comm::Ack {
+                from: 0x0102,
+                id: 0x0304,
+            },
+            &[21, 2, 1, 0, 0, 0, 0, 0, 0, 4, 3, 0, 0, 0, 0, 0, 0],
+        );
+        assert_golden(
+            Comm::Resend {
+                from: 0x0102,
+                seq: 0x0304,
+            },
+            &[28, 2, 1, 0, 0, 0, 0, 0, 0, 4, 3, 0, 0, 0, 0, 0, 0],
+        );
+        let mut bytes = vec![32, 2, 1, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&[6, 5, 0, 0, 0, 0, 0, 0, 4, 3, 0, 0, 0, 0, 0, 0]);
+        assert_golden(
+            Comm::DeliveryStart {
+                from: 0x0102,
+                epoch: 0x0506,
+                seq: 0x0304,
+            },
+            &bytes,
+        );
+    }
+
+    #[test]
+    fn golden_request_and_response() {
+        assert_golden(
+            Comm::Request {
+                id: 0x0102,
+                request: Box::new(Comm::AddFriend(3)),
+            },
+            &[24, 2, 1, 0, 0, 10, 3, 0, 0, 0, 0, 0, 0, 0],
+        );
+        assert_golden(
+            Comm::Response {
+                id: 0x0102,
+                response: Box::new(Comm::Rejected(CommError::InvalidUserId)),
+            },
+            &[25, 2, 1, 0, 0, 4, 1],
+        );
+    }
+
+    #[test]
+    fn nested_request() {
+        let mut buffer = vec![24, 1, 0, 0, 0, 25, 2, 0, 0, 0, 3];
+        assert_eq!(
+            Comm::deserialize(&buffer),
+            Err(SerializeError::UnknownSignature(25))
+        );
+
+        // Deeply nested frame is rejected before it can overflow stack.
+        buffer = [24, 0, 0, 0, 0].repeat(1 << 20);
+        buffer.push(3);
+        assert_eq!(
+            Comm::deserialize(&buffer),
+            Err(SerializeError::UnknownSignature(24))
+        );
+    }
+
+    #[test]
+    fn golden_auth_params() {
+        let salt: [u8; crate::AUTH_SALT_LEN] = std::array::from_fn(|i| i as u8);
+        let mut bytes = vec![31];
+        bytes.extend_from_slice(&salt);
+        bytes.extend_from_slice(&[0xA0, 0x86, 0x01, 0]);
+        assert_golden(
+            Comm::AuthParams(AuthParams {
+                salt,
+                iterations: 100_000,
+            }),
+            &bytes,
+        );
+    }
+
+    #[test]
+    fn golden_hello() {
+        assert_golden(
+            Comm::Hello(Hello::with_versions(1, 2, 0x0304)),
+            &[12, 1, 0, 2, 0, 4, 3, 0, 0],
+        );
+    }
+
+    #[test]
+    fn golden_limits() {
+        let limits = ServerLimits {
+            min_pass_char_len: 1,
+            max_pass_byte_len: 2,
+            max_message_byte_len: 3,
+            max_frame_len: 4,
+        };
+        assert_golden(
+            Comm::Limits(limits),
+            &[13, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0],
+        );
+    }
+
+    #[test]
+    fn golden_session_and_resume() {
+        let token: [u8; SESSION_TOKEN_LEN] = std::array::from_fn(|i| i as u8);
+        let mut bytes = vec![14];
+        bytes.extend_from_slice(&token);
+        bytes.extend_from_slice(&[0x01, 0x02, 0, 0, 0, 0, 0, 0]);
+        assert_golden(
+            Comm::Session {
+                token: SessionToken::from_bytes(token),
+                expires_at: time_from_millis(0x0201),
+            },
+            &bytes,
+        );
+
+        bytes.truncate(1 + SESSION_TOKEN_LEN);
+        bytes[0] = 15;
+        assert_golden(
+            Comm::Resume {
+                token: SessionToken::from_bytes(token),
+            },
+            &bytes,
+        );
+    }
+
+    #[test]
+    fn golden_register() {
+        let mut bytes = vec![16, 0x34, 0x12, 0, 0, 0, 0, 0, 0];
+        bytes.extend(verifier().serialize_to_vec().unwrap());
+        bytes.extend_from_slice(&[2, b'A', b'l']);
+        assert_golden(
+            Comm::Register {
+                id: 0x1234,
+                verifier: verifier(),
+                name: "Al".to_string(),
+            },
+            &bytes,
+        );
+    }
+
+    #[test]
+    fn golden_keys() {
+        let key: PublicKey = std::array::from_fn(|i| i as u8);
+        let mut bytes = vec![17];
+        bytes.extend_from_slice(&key);
+        assert_golden(Comm::PublishKey(key), &bytes);
+
+        let mut bytes = vec![19, 0x34, 0x12, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&key);
+        assert_golden(Comm::UserKey { id: 0x1234, key }, &bytes);
+    }
+
+    #[test]
+    fn register_name_too_long() {
+        let comm = Comm::Register {
+            id: 1,
+            verifier: verifier(),
+            name: "a".repeat(256),
+        };
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        assert_eq!(
+            comm.serialize(&mut buffer),
+            Err(SerializeError::StringTooLong {
+                type_name: "Comm".into(),
+                offset: 1 + USER_ID_SIZE + AuthVerifier::BYTE_LEN,
+                len: 256,
+                max: 255
+            })
+        );
+    }
+
+    #[test]
+    fn change_password_policy() {
+        let policy = PasswordPolicy::default();
+        let params = AuthParams::with_iterations(1);
+        let nonce = crate::new_nonce();
+        let comm = Comm::change_password(1, "new_password", "old", &params, &nonce, &policy);
+        match comm {
+            Ok(Comm::ChangePassword(change)) => {
+                let old = AuthVerifier::with_params("old", params);
+                let verifier = change.open(&old, 1, &nonce).unwrap();
+                assert!(verifier.check_password("new_password"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            Comm::change_password(
+                1,
+                &"a".repeat(MAX_PASS_BYTE_LEN + 1),
+                "old",
+                &params,
+                &nonce,
+                &policy
+            ),
+            Err(PasswordError::TooLong {
+                max: MAX_PASS_BYTE_LEN,
+                actual: MAX_PASS_BYTE_LEN + 1
+            })
+        );
+    }
+
+    #[test]
+    fn register_policy() {
+        let policy = PasswordPolicy::default();
+        match Comm::register(1, "abcd", "Al".to_string(), &policy) {
+            Ok(Comm::Register { id, verifier, name }) => {
+                assert_eq!((id, name.as_str()), (1, "Al"));
+                assert!(verifier.check_password("abcd"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            Comm::register(1, "abc", "Al".to_string(), &policy),
+            Err(PasswordError::TooShort { min: 4, actual: 3 })
+        );
+    }
+
+    #[test]
+    fn unknown_signature() {
+        assert_eq!(
+            Comm::deserialize(&[0xFF]),
+            Err(SerializeError::UnknownSignature(0xFF))
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_offset() {
+        let comm = Comm::Register {
+            id: 1,
+            verifier: verifier(),
+            name: "Al".to_string(),
+        };
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        comm.serialize(&mut buffer).unwrap();
+        let name = 1 + USER_ID_SIZE + AuthVerifier::BYTE_LEN + 1;
+        buffer[name + 1] = 0xFF;
+        assert_eq!(
+            Comm::deserialize(&buffer),
+            Err(SerializeError::InvalidUtf8 {
+                type_name: "Comm".into(),
+                offset: name + 1
+            })
+        );
+
+        // Nested types report offset in their own data.
+        let comm = Comm::Message(Message::new("Hi".to_string(), 1, 2));
+        comm.serialize(&mut buffer).unwrap();
+        buffer[1 + 2 * USER_ID_SIZE + 18] = 0xFF;
+        assert_eq!(
+            Comm::deserialize(&buffer),
+            Err(SerializeError::InvalidUtf8 {
+                type_name: "Message".into(),
+                offset: 2 * USER_ID_SIZE + 18
+            })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        for (comm, _) in all_variants() {
+            if let Comm::Session { .. } | Comm::Resume { .. } = comm {
+                continue;
+            }
+            let json = serde_json::to_string(&comm).unwrap();
+            assert_eq!(serde_json::from_str::<Comm>(&json).unwrap(), comm);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_skips_tokens() {
+        let token = SessionToken::from_bytes([0xAB; SESSION_TOKEN_LEN]);
+        let comm = Comm::Resume {
+            token: token.clone(),
+        };
+        let json = serde_json::to_string(&comm).unwrap();
+        assert!(!json.contains("171"), "{}", json);
+        match serde_json::from_str::<Comm>(&json).unwrap() {
+            Comm::Resume { token: read } => assert_ne!(read, token),
+            other => panic!("{:?}", other),
+        }
+
+        let comm = Comm::Session {
+            token,
+            expires_at: time_from_millis(1_600_000_000_000),
+        };
+        let json = serde_json::to_string(&comm).unwrap();
+        assert!(!json.contains("171"), "{}", json);
     }
 }
@@ -1,7 +1,17 @@
-use crate::serialize::{Serialize, SerializeError};
+use crate::{
+    serialize::{Serialize, SerializeError},
+    UserID, USER_ID_SIZE,
+};
+use std::{convert::TryInto, error::Error, fmt, time::Duration};
 
-/// Comunnication errors.
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Comunnication errors. Some of them carry context, e.g. user that caused them or time after
+/// which client can try again.
+///
+/// Only `Custom` carries human-readable reason. Other variants describe known failures, so their
+/// meaning is given by variant itself and client can show its own, e.g. translated, text for them.
+/// Server uses `Custom` when there is nothing more specific to say.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommError {
     /// Used only during login procedure.
     BadLoginData,
@@ -15,30 +25,241 @@ pub enum CommError {
     /// Other invalid operation.
     InvalidOperation,
 
+    /// Client and server don't have any common protocol version.
+    VersionMismatch,
+
+    /// Used during registration when ID already belongs to other account or was reserved for
+    /// other client.
+    IdTaken,
+
+    /// Used during registration when ID reservation expired before `Register` was received.
+    IdExpired,
+
+    /// Password is too short. Server can't check passwords, because it receives only login
+    /// verifiers, so client uses it for passwords rejected by its own `PasswordPolicy`.
+    WeakPassword,
+
+    /// Operation requires given user to be a friend, e.g. `RemoveFriend`.
+    NotFriend(UserID),
+
+    /// Given user is already a friend, so it can't be invited or added again.
+    AlreadyFriend(UserID),
+
+    /// Given user didn't send invitation that could be accepted or removed.
+    NoInvitation(UserID),
+
+    /// Given user isn't logged in, e.g. when requesting something that needs its answer.
+    UserOffline(UserID),
+
+    /// Client sends requests too often and should wait before sending next one.
+    RateLimited {
+        /// Time after which client can try again. It's sent in whole milliseconds, rounded up.
+        retry_after: Duration,
+    },
+
+    /// Server can't accept more clients now.
+    ServerFull {
+        /// Time after which client can try to connect again. It's sent in whole milliseconds,
+        /// rounded up.
+        retry_after: Duration,
+    },
+
+    /// Message content is longer than `ServerLimits::max_message_byte_len`.
+    MessageTooLong,
+
+    /// Error that doesn't fit any other variant, with reason that can be shown to user. Reason
+    /// can't be longer than 255 bytes.
+    Custom(String),
+
     /// Unknown
     Unknown, // This should be last option
 }
 
-impl Serialize for CommError {
-    type Item = CommError;
+impl CommError {
+    /// Returns number that identifies variant on the wire. Variants added after `Unknown` get
+    /// numbers after it, so older peers still read the first ones.
+    fn tag(&self) -> u8 {
+        match self {
+            CommError::BadLoginData => 0,
+            CommError::InvalidUserId => 1,
+            CommError::InvalidPassword => 2,
+            CommError::InvalidOperation => 3,
+            CommError::Unknown => 4,
+            CommError::VersionMismatch => 5,
+            CommError::IdTaken => 6,
+            CommError::IdExpired => 7,
+            CommError::WeakPassword => 8,
+            CommError::NotFriend(_) => 9,
+            CommError::AlreadyFriend(_) => 10,
+            CommError::NoInvitation(_) => 11,
+            CommError::UserOffline(_) => 12,
+            CommError::RateLimited { .. } => 13,
+            CommError::ServerFull { .. } => 14,
+            CommError::MessageTooLong => 15,
+            CommError::Custom(_) => 16,
+        }
+    }
+
+    /// Returns user that caused error, if error carries it.
+    pub fn user(&self) -> Option<UserID> {
+        match self {
+            CommError::NotFriend(id)
+            | CommError::AlreadyFriend(id)
+            | CommError::NoInvitation(id)
+            | CommError::UserOffline(id) => Some(*id),
+            _ => None,
+        }
+    }
 
-    /// Converts CommError to `u8` number and writes it to `buffer[0]`.
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), SerializeError> {
-        Ok(buffer[0] = *self as u8)
+    /// Returns time after which client can try again, if error carries it.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            CommError::RateLimited { retry_after } | CommError::ServerFull { retry_after } => {
+                Some(*retry_after)
+            }
+            _ => None,
+        }
     }
 
-    /// Reads `buffer[0]` and creates CommError object from it.
-    fn deserialize(buffer: &[u8]) -> Result<Self::Item, SerializeError> {
-        match buffer[0] {
-            0 => Ok(CommError::BadLoginData),
-            1 => Ok(CommError::InvalidUserId),
-            2 => Ok(CommError::InvalidPassword),
-            3 => Ok(CommError::InvalidOperation),
-            sig => Err(SerializeError::UnknownSignature(sig)),
+    /// Returns reason that can be shown to user, if error carries it.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            CommError::Custom(reason) => Some(reason),
+            _ => None,
         }
     }
 }
 
+/// Returns `duration` in whole milliseconds, rounded up so client never retries too early.
+fn millis_ceil(duration: Duration) -> u64 {
+    let millis = duration.as_nanos().div_ceil(1_000_000);
+    millis.try_into().unwrap_or(u64::MAX)
+}
+
+impl fmt::Display for CommError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommError::BadLoginData => write!(f, "invalid login data"),
+            CommError::InvalidUserId => write!(f, "invalid user ID"),
+            CommError::InvalidPassword => write!(f, "invalid password"),
+            CommError::InvalidOperation => write!(f, "invalid operation"),
+            CommError::VersionMismatch => write!(f, "no common protocol version"),
+            CommError::IdTaken => write!(f, "user ID is already taken"),
+            CommError::IdExpired => write!(f, "user ID reservation expired"),
+            CommError::WeakPassword => write!(f, "password is too weak"),
+            CommError::NotFriend(id) => write!(f, "user {} is not a friend", id),
+            CommError::AlreadyFriend(id) => write!(f, "user {} is already a friend", id),
+            CommError::NoInvitation(id) => write!(f, "no invitation from user {}", id),
+            CommError::UserOffline(id) => write!(f, "user {} is offline", id),
+            CommError::RateLimited { retry_after } => {
+                write!(f, "too many requests, retry after {:?}", retry_after)
+            }
+            CommError::ServerFull { retry_after } => {
+                write!(f, "server is full, retry after {:?}", retry_after)
+            }
+            CommError::MessageTooLong => write!(f, "message is too long"),
+            CommError::Custom(reason) => write!(f, "{}", reason),
+            CommError::Unknown => write!(f, "unknown error"),
+        }
+    }
+}
+
+impl Error for CommError {}
+
+impl Serialize for CommError {
+    fn encoded_len(&self) -> usize {
+        1 + match self {
+            CommError::NotFriend(_)
+            | CommError::AlreadyFriend(_)
+            | CommError::NoInvitation(_)
+            | CommError::UserOffline(_) => USER_ID_SIZE,
+            CommError::RateLimited { .. } | CommError::ServerFull { .. } => 8,
+            CommError::Custom(reason) => 1 + reason.len(),
+            _ => 0,
+        }
+    }
+
+    /// Writes variant number to `buffer[0]`, followed by context of variant. `UserID` and
+    /// retry-after time in milliseconds, rounded up, take 8 bytes, reason of `Custom` takes 1 byte length and
+    /// UTF-8 bytes.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        let (tag, buffer) = buffer
+            .split_first_mut()
+            .ok_or(SerializeError::NotEnoughData)?;
+        *tag = self.tag();
+
+        let len = match self {
+            CommError::NotFriend(id)
+            | CommError::AlreadyFriend(id)
+            | CommError::NoInvitation(id)
+            | CommError::UserOffline(id) => {
+                crate::write_bytes_to_buffer(buffer, &id.to_le_bytes())?
+            }
+
+            CommError::RateLimited { retry_after } | CommError::ServerFull { retry_after } => {
+                crate::write_bytes_to_buffer(buffer, &millis_ceil(*retry_after).to_le_bytes())?
+            }
+
+            CommError::Custom(reason) => {
+                // Reason longer than u8 can't be described by length field.
+                if reason.len() > u8::MAX as usize {
+                    return Err(SerializeError::StringTooLong {
+                        type_name: "CommError".into(),
+                        offset: 1,
+                        len: reason.len(),
+                        max: u8::MAX as usize,
+                    });
+                }
+                let index = crate::write_bytes_to_buffer(buffer, &[reason.len() as u8])?;
+                index + crate::write_bytes_to_buffer(&mut buffer[index..], reason.as_bytes())?
+            }
+
+            _ => 0,
+        };
+        Ok(1 + len)
+    }
+
+    /// Reads variant number from `buffer[0]` and its context from following bytes.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        let (tag, buffer) = buffer.split_first().ok_or(SerializeError::NotEnoughData)?;
+        let id = || crate::parse_id_from_bytes(buffer);
+        let retry_after = || id().map(Duration::from_millis);
+
+        let error = match tag {
+            0 => CommError::BadLoginData,
+            1 => CommError::InvalidUserId,
+            2 => CommError::InvalidPassword,
+            3 => CommError::InvalidOperation,
+            4 => CommError::Unknown,
+            5 => CommError::VersionMismatch,
+            6 => CommError::IdTaken,
+            7 => CommError::IdExpired,
+            8 => CommError::WeakPassword,
+            9 => CommError::NotFriend(id()?),
+            10 => CommError::AlreadyFriend(id()?),
+            11 => CommError::NoInvitation(id()?),
+            12 => CommError::UserOffline(id()?),
+            13 => CommError::RateLimited {
+                retry_after: retry_after()?,
+            },
+            14 => CommError::ServerFull {
+                retry_after: retry_after()?,
+            },
+            15 => CommError::MessageTooLong,
+            16 => {
+                let (len, buffer) = buffer.split_first().ok_or(SerializeError::NotEnoughData)?;
+                let reason = buffer
+                    .get(..*len as usize)
+                    .ok_or(SerializeError::NotEnoughData)?;
+                CommError::Custom(crate::parse_utf8(reason, "CommError", 2)?.to_string())
+            }
+            &sig => return Err(SerializeError::UnknownSignature(sig)),
+        };
+        let len = error.encoded_len();
+        Ok((error, len))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,7 +269,7 @@ mod tests {
         let mut buffer = [0xFF];
         let e1 = CommError::BadLoginData;
         e1.serialize(&mut buffer).unwrap();
-        let e2 = CommError::deserialize(&buffer).unwrap();
+        let e2 = CommError::deserialize(&buffer).unwrap().0;
         assert_eq!(e1, e2);
     }
 
@@ -57,7 +278,7 @@ mod tests {
         let mut buffer = [0xFF];
         let e1 = CommError::InvalidUserId;
         e1.serialize(&mut buffer).unwrap();
-        let e2 = CommError::deserialize(&buffer).unwrap();
+        let e2 = CommError::deserialize(&buffer).unwrap().0;
         assert_eq!(e1, e2);
     }
 
@@ -66,7 +287,7 @@ mod tests {
         let mut buffer = [0xFF];
         let e1 = CommError::InvalidPassword;
         e1.serialize(&mut buffer).unwrap();
-        let e2 = CommError::deserialize(&buffer).unwrap();
+        let e2 = CommError::deserialize(&buffer).unwrap().0;
         assert_eq!(e1, e2);
     }
 
@@ -75,10 +296,201 @@ mod tests {
         let mut buffer = [0xFF];
         let e1 = CommError::InvalidOperation;
         e1.serialize(&mut buffer).unwrap();
-        let e2 = CommError::deserialize(&buffer).unwrap();
+        let e2 = CommError::deserialize(&buffer).unwrap().0;
         assert_eq!(e1, e2);
     }
 
+    #[test]
+    fn version_mismatch() {
+        let mut buffer = [0xFF];
+        let e1 = CommError::VersionMismatch;
+        e1.serialize(&mut buffer).unwrap();
+        let e2 = CommError::deserialize(&buffer).unwrap().0;
+        assert_eq!(e1, e2);
+    }
+
+    #[test]
+    fn golden_baseline_tags() {
+        // These tags were used before any variant had context and must never change.
+        for (error, tag) in [
+            (CommError::BadLoginData, 0),
+            (CommError::InvalidUserId, 1),
+            (CommError::InvalidPassword, 2),
+            (CommError::InvalidOperation, 3),
+            (CommError::Unknown, 4),
+        ] {
+            let mut buffer = [0xFF];
+            error.serialize(&mut buffer).unwrap();
+            assert_eq!(buffer, [tag], "{:?}", error);
+            assert_eq!(CommError::deserialize(&buffer).unwrap().0, error);
+        }
+    }
+
+    #[test]
+    fn registration_errors() {
+        for (e1, sig) in [
+            (CommError::IdTaken, 6),
+            (CommError::IdExpired, 7),
+            (CommError::WeakPassword, 8),
+        ] {
+            let mut buffer = [0xFF];
+            e1.serialize(&mut buffer).unwrap();
+            assert_eq!(buffer, [sig]);
+            assert_eq!(CommError::deserialize(&buffer).unwrap().0, e1);
+        }
+    }
+
+    /// Returns all variants ordered by their tag with their wire length.
+    fn all_variants() -> Vec<(CommError, usize)> {
+        vec![
+            (CommError::BadLoginData, 1),
+            (CommError::InvalidUserId, 1),
+            (CommError::InvalidPassword, 1),
+            (CommError::InvalidOperation, 1),
+            (CommError::Unknown, 1),
+            (CommError::VersionMismatch, 1),
+            (CommError::IdTaken, 1),
+            (CommError::IdExpired, 1),
+            (CommError::WeakPassword, 1),
+            (CommError::NotFriend(1), 9),
+            (CommError::AlreadyFriend(2), 9),
+            (CommError::NoInvitation(3), 9),
+            (CommError::UserOffline(4), 9),
+            (
+                CommError::RateLimited {
+                    retry_after: Duration::from_millis(1500),
+                },
+                9,
+            ),
+            (
+                CommError::ServerFull {
+                    retry_after: Duration::from_secs(60),
+                },
+                9,
+            ),
+            (CommError::MessageTooLong, 1),
+            (CommError::Custom("Server is restarting".to_string()), 22),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for (tag, (e1, len)) in all_variants().into_iter().enumerate() {
+            let mut buffer = [0xFF; 32];
+            e1.serialize(&mut buffer).unwrap();
+            assert_eq!(buffer[0] as usize, tag, "{:?}", e1);
+            assert_eq!(CommError::deserialize(&buffer).unwrap().0, e1);
+            // Shorter buffer can't hold all context.
+            if len > 1 {
+                assert_eq!(
+                    CommError::deserialize(&buffer[..len - 1]),
+                    Err(SerializeError::NotEnoughData)
+                );
+                assert_eq!(
+                    e1.serialize(&mut buffer[..len - 1]),
+                    Err(SerializeError::NotEnoughData)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn golden_context() {
+        let mut buffer = [0u8; 9];
+        CommError::NotFriend(0x0102).serialize(&mut buffer).unwrap();
+        assert_eq!(buffer, [9, 0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+
+        let e = CommError::RateLimited {
+            retry_after: Duration::from_millis(0x0304),
+        };
+        e.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer, [13, 0x04, 0x03, 0, 0, 0, 0, 0, 0]);
+
+        let mut buffer = [0u8; 4];
+        CommError::Custom("ab".to_string())
+            .serialize(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [16, 2, b'a', b'b']);
+    }
+
+    #[test]
+    fn custom_reason() {
+        let mut buffer = [0u8; 300];
+        assert_eq!(
+            CommError::Custom("a".repeat(256)).serialize(&mut buffer),
+            Err(SerializeError::StringTooLong {
+                type_name: "CommError".into(),
+                offset: 1,
+                len: 256,
+                max: 255
+            })
+        );
+        assert_eq!(
+            CommError::deserialize(&[16, 2, b'a', 0xFF]),
+            Err(SerializeError::InvalidUtf8 {
+                type_name: "CommError".into(),
+                offset: 3
+            })
+        );
+    }
+
+    #[test]
+    fn context() {
+        assert_eq!(CommError::UserOffline(5).user(), Some(5));
+        assert_eq!(CommError::InvalidUserId.user(), None);
+        let retry_after = Duration::from_secs(3);
+        assert_eq!(
+            CommError::ServerFull { retry_after }.retry_after(),
+            Some(retry_after)
+        );
+        assert_eq!(CommError::NotFriend(1).retry_after(), None);
+        assert_eq!(CommError::Custom("Bye".to_string()).reason(), Some("Bye"));
+        assert_eq!(CommError::InvalidOperation.reason(), None);
+    }
+
+    #[test]
+    fn retry_after_rounded_up() {
+        for (retry_after, millis) in [
+            (Duration::from_nanos(0), 0),
+            (Duration::from_nanos(1), 1),
+            (Duration::from_micros(999), 1),
+            (Duration::from_millis(1), 1),
+            (Duration::from_micros(1001), 2),
+            (Duration::new(2, 1), 2001),
+            (Duration::MAX, u64::MAX),
+        ] {
+            let e1 = CommError::RateLimited { retry_after };
+            let mut buffer = [0u8; 9];
+            e1.serialize(&mut buffer).unwrap();
+            assert_eq!(buffer[1..], u64::to_le_bytes(millis), "{:?}", retry_after);
+            let e2 = CommError::deserialize(&buffer).unwrap().0;
+            assert_eq!(
+                e2.retry_after(),
+                Some(Duration::from_millis(millis)),
+                "{:?}",
+                retry_after
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            CommError::NotFriend(7).to_string(),
+            "user 7 is not a friend"
+        );
+        assert_eq!(
+            CommError::RateLimited {
+                retry_after: Duration::from_secs(2)
+            }
+            .to_string(),
+            "too many requests, retry after 2s"
+        );
+        assert_eq!(CommError::Custom("Bye".to_string()).to_string(), "Bye");
+        let error: Box<dyn Error> = Box::new(CommError::Unknown);
+        assert_eq!(error.to_string(), "unknown error");
+    }
+
     #[test]
     fn unknown_signature() {
         let buffer = [0xFF];
@@ -87,4 +499,17 @@ mod tests {
             Err(e) => assert_eq!(e, SerializeError::UnknownSignature(0xFF)),
         }
     }
+
+    #[test]
+    fn empty_buffer() {
+        let mut buffer = [];
+        assert_eq!(
+            CommError::BadLoginData.serialize(&mut buffer),
+            Err(SerializeError::NotEnoughData)
+        );
+        assert_eq!(
+            CommError::deserialize(&buffer),
+            Err(SerializeError::NotEnoughData)
+        );
+    }
 }
@@ -0,0 +1,224 @@
+use crate::{frame, Comm, FrameError, FrameReader, Serialize, ServerLimits, UserID};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::Duration,
+};
+
+/// Streams that can have read and write timeouts set and can be shut down. It is implemented for
+/// `TcpStream` and on Unix also for `UnixStream`.
+pub trait Socket {
+    /// Sets read timeout. `None` means that read will block forever.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Sets write timeout. `None` means that write will block forever.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Shuts down both reading and writing half of stream.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Blocking connection that sends and receives `Comm` over any stream using frames compatible with
+/// `FrameWriter` and `FrameReader`.
+pub struct Connection<S: Read + Write> {
+    reader: FrameReader<S>,
+    limits: ServerLimits,
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// Creates connection that uses default `ServerLimits`.
+    pub fn new(stream: S) -> Self {
+        Self::with_limits(stream, ServerLimits::default())
+    }
+
+    /// Creates connection that uses maximum frame size from `limits`.
+    pub fn with_limits(stream: S, limits: ServerLimits) -> Self {
+        Self {
+            reader: FrameReader::with_max_frame_len(stream, limits.frame_len()),
+            limits,
+        }
+    }
+
+    /// Returns limits used by connection.
+    pub fn limits(&self) -> &ServerLimits {
+        &self.limits
+    }
+
+    /// Replaces limits, e.g. after `Comm::Limits` was received from server.
+    pub fn set_limits(&mut self, limits: ServerLimits) {
+        self.reader.set_max_frame_len(limits.frame_len());
+        self.limits = limits;
+    }
+
+    /// Returns reference to underlying stream.
+    pub fn get_ref(&self) -> &S {
+        self.reader.get_ref()
+    }
+
+    /// Returns mutable reference to underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.reader.get_mut()
+    }
+
+    /// Consumes connection and returns underlying stream.
+    pub fn into_inner(self) -> S {
+        self.reader.into_inner()
+    }
+
+    /// Sends `comm` as single frame.
+    pub fn send(&mut self, comm: &Comm) -> Result<(), FrameError> {
+        let buffer = comm.serialize_to_vec()?;
+        frame::write_frame(self.reader.get_mut(), &buffer, self.limits.frame_len())
+    }
+
+    /// Waits for next `Comm`. Returns `FrameError::Io` with `ErrorKind::UnexpectedEof` if peer
+    /// closed connection. When read timeout expires the error is returned, but bytes read so far
+    /// are kept, so `recv` can be called again.
+    pub fn recv(&mut self) -> Result<Comm, FrameError> {
+        match self.reader.read_frame()? {
+            Some(frame) => Ok(Comm::deserialize_exact(&frame)?),
+            None => Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+impl<S: Read + Write + Socket> Connection<S> {
+    /// Sets read timeout used by `recv`. `None` means that `recv` will block forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    /// Sets write timeout used by `send`. `None` means that `send` will block forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+
+    /// Sends `Comm::Disconnected(id)` and shuts down stream. Stream is shut down even if sending
+    /// failed.
+    pub fn close(mut self, id: UserID) -> Result<(), FrameError> {
+        let sent = self.send(&Comm::Disconnected(id));
+        let shutdown = self.get_ref().shutdown();
+        sent?;
+        match shutdown {
+            // Peer could have already closed its side.
+            Err(e) if e.kind() != ErrorKind::NotConnected => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use std::{os::unix::net::UnixStream, thread};
+
+    #[test]
+    fn send_and_recive() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut client = Connection::new(a);
+        let mut server = Connection::new(b);
+
+        client.send(&Comm::AddFriend(2)).unwrap();
+        client
+            .send(&Comm::Message(Message::new("Hi".to_string(), 1, 2)))
+            .unwrap();
+        assert_eq!(server.recv().unwrap(), Comm::AddFriend(2));
+        match server.recv().unwrap() {
+            Comm::Message(msg) => assert_eq!(msg.content(), "Hi"),
+            other => panic!("{:?}", other),
+        }
+
+        server.send(&Comm::Accepted).unwrap();
+        assert_eq!(client.recv().unwrap(), Comm::Accepted);
+    }
+
+    #[test]
+    fn read_timeout() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut client = Connection::new(a);
+        client
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        match client.recv() {
+            Err(FrameError::Io(e)) => {
+                assert!(e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn close() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let client = Connection::new(a);
+        let mut server = Connection::new(b);
+
+        let handle = thread::spawn(move || client.close(7));
+        assert_eq!(server.recv().unwrap(), Comm::Disconnected(7));
+        match server.recv() {
+            Err(FrameError::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            other => panic!("{:?}", other),
+        }
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn custom_limits() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let limits = ServerLimits {
+            max_frame_len: 8192,
+            ..Default::default()
+        };
+        let mut client = Connection::with_limits(a, limits);
+        let mut server = Connection::new(b);
+        let message = Comm::Message(Message::new("a".repeat(6000), 1, 2));
+
+        client.send(&message).unwrap();
+        // Server doesn't use the same limits yet, so frame is too large.
+        match server.recv() {
+            Err(FrameError::TooLarge(len, max)) => {
+                assert_eq!(len, message.encoded_len());
+                assert_eq!(max, ServerLimits::default().frame_len());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut client = Connection::with_limits(a, limits);
+        let mut server = Connection::new(b);
+        server.set_limits(limits);
+        client.send(&message).unwrap();
+        assert_eq!(server.recv().unwrap(), message);
+    }
+}
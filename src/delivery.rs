@@ -0,0 +1,661 @@
+use crate::{Comm, CommError, Message, MessageId, SealedMessage, UserID};
+use std::collections::VecDeque;
+
+/// Sequence number of message in single conversation. First message has number 1, so
+/// `Comm::AckUpTo` with 0 doesn't acknowledge anything.
+pub type SeqNum = u64;
+
+/// Default number of messages that can be delivered without acknowledgement.
+pub const DEFAULT_DELIVERY_WINDOW: usize = 64;
+
+/// Message stored by server until recipient acknowledges it, in `Outbox` or `MessageQueue`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pending {
+    /// Message received in `Comm::Message`.
+    Plain(Message),
+
+    /// End-to-end encrypted message received in `Comm::SealedMessage`.
+    Sealed(SealedMessage),
+}
+
+impl Pending {
+    /// Returns sender ID.
+    pub fn from(&self) -> &UserID {
+        match self {
+            Pending::Plain(message) => message.from(),
+            Pending::Sealed(message) => message.from(),
+        }
+    }
+
+    /// Returns reciever ID.
+    pub fn to(&self) -> &UserID {
+        match self {
+            Pending::Plain(message) => message.to(),
+            Pending::Sealed(message) => message.to(),
+        }
+    }
+
+    /// Returns message ID.
+    pub fn id(&self) -> MessageId {
+        match self {
+            Pending::Plain(message) => message.id(),
+            Pending::Sealed(message) => message.id(),
+        }
+    }
+
+    /// Returns `Comm::Message` or `Comm::SealedMessage` that delivers message to recipient.
+    pub fn to_comm(&self) -> Comm {
+        match self {
+            Pending::Plain(message) => Comm::Message(message.clone()),
+            Pending::Sealed(message) => Comm::SealedMessage(message.clone()),
+        }
+    }
+}
+
+impl From<Message> for Pending {
+    fn from(message: Message) -> Self {
+        Pending::Plain(message)
+    }
+}
+
+impl From<SealedMessage> for Pending {
+    fn from(message: SealedMessage) -> Self {
+        Pending::Sealed(message)
+    }
+}
+
+/// Returns new random epoch of `Outbox`.
+///
+/// # Panics
+/// This function will panic if operating system can't provide random bytes.
+fn new_epoch() -> u64 {
+    let mut epoch = [0u8; 8];
+    getrandom::getrandom(&mut epoch).expect("Can't generate random epoch.");
+    u64::from_le_bytes(epoch)
+}
+
+/// Server side queue of messages stored for one recipient from one sender. Messages are delivered
+/// with `Comm::Deliver` or `Comm::DeliverSealed` without waiting for each acknowledgement, until
+/// `window` of them is in flight. Recipient confirms all of them at once with `Comm::AckUpTo`, so
+/// throughput doesn't depend on latency.
+///
+/// Every outbox has random epoch. Server sends it with `Comm::DeliveryStart` returned by `start`
+/// each time recipient connects, so `Inbox` notices when server lost its outbox and numbering
+/// started again. Outbox can be stored with serde to keep numbering across server restarts,
+/// messages in flight are then delivered again.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Outbox {
+    from: UserID,
+    window: usize,
+    epoch: u64,
+    next_seq: SeqNum,
+    // Not acknowledged messages in order of sequence numbers. First `in_flight` of them were
+    // already sent.
+    pending: VecDeque<(SeqNum, Pending)>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    in_flight: usize,
+}
+
+impl Outbox {
+    /// Creates empty outbox for messages from `from` with `DEFAULT_DELIVERY_WINDOW`.
+    pub fn new(from: UserID) -> Self {
+        Self::with_window(from, DEFAULT_DELIVERY_WINDOW)
+    }
+
+    /// Creates empty outbox for messages from `from` that allows `window` messages in flight.
+    ///
+    /// # Panics
+    /// This function will panic if `window` is 0 or if operating system can't provide random
+    /// bytes for epoch.
+    pub fn with_window(from: UserID, window: usize) -> Self {
+        assert!(window > 0, "Delivery window can't be empty.");
+        Self {
+            from,
+            window,
+            epoch: new_epoch(),
+            next_seq: 1,
+            pending: VecDeque::new(),
+            in_flight: 0,
+        }
+    }
+
+    /// Returns sender of messages in outbox.
+    pub fn from(&self) -> UserID {
+        self.from
+    }
+
+    /// Returns random number that identifies this outbox.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns maximum number of messages in flight.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Changes maximum number of messages in flight. Messages already in flight stay there.
+    ///
+    /// # Panics
+    /// This function will panic if `window` is 0.
+    pub fn set_window(&mut self, window: usize) {
+        assert!(window > 0, "Delivery window can't be empty.");
+        self.window = window;
+    }
+
+    /// Stores message and returns its sequence number.
+    pub fn push(&mut self, message: Message) -> SeqNum {
+        self.push_pending(Pending::Plain(message))
+    }
+
+    /// Stores end-to-end encrypted message and returns its sequence number. It shares sequence
+    /// numbers with plain messages from the same sender.
+    pub fn push_sealed(&mut self, message: SealedMessage) -> SeqNum {
+        self.push_pending(Pending::Sealed(message))
+    }
+
+    fn push_pending(&mut self, message: Pending) -> SeqNum {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pending.push_back((seq, message));
+        seq
+    }
+
+    /// Returns next `Comm::Deliver` or `Comm::DeliverSealed` that should be sent, or `None` if
+    /// window is full or there is nothing more to send.
+    pub fn next_delivery(&mut self) -> Option<Comm> {
+        if self.in_flight >= self.window {
+            return None;
+        }
+
+        let (seq, message) = self.pending.get(self.in_flight)?;
+        self.in_flight += 1;
+        let seq = *seq;
+        Some(match message {
+            Pending::Plain(message) => Comm::Deliver {
+                seq,
+                message: message.clone(),
+            },
+            Pending::Sealed(message) => Comm::DeliverSealed {
+                seq,
+                message: message.clone(),
+            },
+        })
+    }
+
+    /// Returns sequence number of last acknowledged message.
+    fn acked(&self) -> SeqNum {
+        self.pending.front().map_or(self.next_seq, |(seq, _)| *seq) - 1
+    }
+
+    /// Removes all messages up to `seq` and returns how many were removed. Returns
+    /// `CommError::InvalidOperation` if `seq` wasn't sent yet.
+    pub fn ack_up_to(&mut self, seq: SeqNum) -> Result<usize, CommError> {
+        let sent_up_to = match self.in_flight {
+            0 => self.acked(),
+            n => self.pending[n - 1].0,
+        };
+        if seq > sent_up_to {
+            return Err(CommError::InvalidOperation);
+        }
+
+        let acked = self
+            .pending
+            .iter()
+            .take_while(|(pending, _)| *pending <= seq)
+            .count();
+        self.pending.drain(..acked);
+        self.in_flight -= acked;
+        Ok(acked)
+    }
+
+    /// Marks all messages in flight as not sent. They will be returned by `next_delivery` again
+    /// with the same sequence numbers.
+    pub fn rewind(&mut self) {
+        self.in_flight = 0;
+    }
+
+    /// Starts delivery after recipient connected. Rewinds messages in flight and returns
+    /// `Comm::DeliveryStart` that must be sent before first delivery, so `Inbox` of recipient
+    /// agrees with outbox on sequence numbers. See `Inbox::start`.
+    pub fn start(&mut self) -> Comm {
+        self.rewind();
+        Comm::DeliveryStart {
+            from: self.from,
+            epoch: self.epoch,
+            seq: self.acked(),
+        }
+    }
+
+    /// Handles `Comm::Resend`. Removes all messages up to `seq` like `ack_up_to` and rewinds the
+    /// rest, so they are delivered again starting from `seq + 1`.
+    pub fn resend(&mut self, seq: SeqNum) -> Result<usize, CommError> {
+        let acked = self.ack_up_to(seq)?;
+        self.rewind();
+        Ok(acked)
+    }
+
+    /// Returns number of messages sent but not acknowledged.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Returns number of messages not acknowledged yet.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns true if all messages were acknowledged.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// What client should do with message delivered to `Inbox`.
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    /// Next message in order, it should be shown to user.
+    New,
+
+    /// Message must be dropped, because it was already received or earlier messages are missing
+    /// and their resend was already requested.
+    Dropped,
+
+    /// Earlier messages are missing. Message must be dropped and this `Comm::Resend` must be sent
+    /// to server, so it delivers them again in order.
+    Resend(Comm),
+}
+
+/// Client side state of messages received from one sender. It drops messages that were delivered
+/// again or out of order and tells when to acknowledge them. Inbox can be stored with serde, but
+/// it doesn't have to be, because `start` syncs it with `Outbox` after reconnect.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Inbox {
+    from: UserID,
+    // Epoch of outbox received in last `Comm::DeliveryStart`.
+    epoch: Option<u64>,
+    received: SeqNum,
+    acked: SeqNum,
+    // Last received sequence number when resend was requested.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    resend_requested: Option<SeqNum>,
+}
+
+impl Inbox {
+    /// Creates inbox for messages from `from`.
+    pub fn new(from: UserID) -> Self {
+        Self {
+            from,
+            epoch: None,
+            received: 0,
+            acked: 0,
+            resend_requested: None,
+        }
+    }
+
+    /// Handles `Comm::DeliveryStart` with `epoch` of outbox and `seq` of last message it knows
+    /// was acknowledged. If outbox is other than before, e.g. because client or server lost its
+    /// state, or it already got acknowledgement of messages that inbox didn't receive, inbox
+    /// continues from `seq`. Otherwise it keeps dropping messages that were already received, but
+    /// acknowledges them again with next `ack`, because acknowledgement could be lost.
+    pub fn start(&mut self, epoch: u64, seq: SeqNum) {
+        if self.epoch != Some(epoch) || self.received < seq {
+            self.received = seq;
+        }
+        self.epoch = Some(epoch);
+        self.acked = seq;
+        self.resend_requested = None;
+    }
+
+    /// Returns sender of messages in inbox.
+    pub fn from(&self) -> UserID {
+        self.from
+    }
+
+    /// Records delivery of message `seq`. Only message that directly follows last received one is
+    /// accepted, so acknowledgement never covers messages that didn't arrive.
+    pub fn receive(&mut self, seq: SeqNum) -> Received {
+        if seq <= self.received {
+            Received::Dropped
+        } else if seq == self.received + 1 {
+            self.received = seq;
+            Received::New
+        } else if self.resend_requested == Some(self.received) {
+            Received::Dropped
+        } else {
+            // Resend confirms everything received so far, just like `AckUpTo`.
+            self.resend_requested = Some(self.received);
+            self.acked = self.received;
+            Received::Resend(Comm::Resend {
+                from: self.from,
+                seq: self.received,
+            })
+        }
+    }
+
+    /// Returns number of received messages that weren't acknowledged yet.
+    pub fn unacked(&self) -> u64 {
+        self.received - self.acked
+    }
+
+    /// Returns `Comm::AckUpTo` for all received messages, or `None` if all were already
+    /// acknowledged. It should be sent at least every half of delivery window and whenever
+    /// there is nothing more to read, so server never stalls.
+    pub fn ack(&mut self) -> Option<Comm> {
+        if self.received == self.acked {
+            return None;
+        }
+
+        self.acked = self.received;
+        Some(Comm::AckUpTo {
+            from: self.from,
+            seq: self.acked,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outbox(window: usize, count: usize) -> Outbox {
+        let mut outbox = Outbox::with_window(1, window);
+        for i in 0..count {
+            assert_eq!(
+                outbox.push(Message::new(i.to_string(), 1, 2)),
+                i as SeqNum + 1
+            );
+        }
+        outbox
+    }
+
+    /// Returns sequence numbers of all deliveries that can be sent now.
+    fn deliver(outbox: &mut Outbox) -> Vec<SeqNum> {
+        std::iter::from_fn(|| outbox.next_delivery())
+            .map(|comm| match comm {
+                Comm::Deliver { seq, .. } => seq,
+                other => panic!("{:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn window() {
+        let mut outbox = outbox(3, 5);
+        assert_eq!(deliver(&mut outbox), [1, 2, 3]);
+        assert_eq!(outbox.in_flight(), 3);
+
+        assert_eq!(outbox.ack_up_to(2), Ok(2));
+        assert_eq!(deliver(&mut outbox), [4, 5]);
+        assert_eq!(outbox.ack_up_to(5), Ok(3));
+        assert!(outbox.is_empty());
+        assert!(deliver(&mut outbox).is_empty());
+    }
+
+    #[test]
+    fn ack_not_sent() {
+        let mut outbox = outbox(2, 3);
+        assert_eq!(outbox.ack_up_to(1), Err(CommError::InvalidOperation));
+        deliver(&mut outbox);
+        assert_eq!(outbox.ack_up_to(3), Err(CommError::InvalidOperation));
+        assert_eq!(outbox.ack_up_to(2), Ok(2));
+        assert_eq!(outbox.len(), 1);
+    }
+
+    #[test]
+    fn repeated_ack() {
+        let mut outbox = outbox(4, 2);
+        deliver(&mut outbox);
+        assert_eq!(outbox.ack_up_to(2), Ok(2));
+        assert_eq!(outbox.ack_up_to(2), Ok(0));
+        assert_eq!(outbox.ack_up_to(0), Ok(0));
+    }
+
+    #[test]
+    fn rewind() {
+        let mut outbox = outbox(2, 3);
+        assert_eq!(deliver(&mut outbox), [1, 2]);
+        outbox.rewind();
+        assert_eq!(outbox.in_flight(), 0);
+        assert_eq!(deliver(&mut outbox), [1, 2]);
+    }
+
+    #[test]
+    fn set_window() {
+        let mut outbox = outbox(1, 3);
+        assert_eq!(deliver(&mut outbox), [1]);
+        outbox.set_window(3);
+        assert_eq!(deliver(&mut outbox), [2, 3]);
+    }
+
+    #[test]
+    fn resend() {
+        let mut outbox = outbox(4, 5);
+        assert_eq!(deliver(&mut outbox), [1, 2, 3, 4]);
+        assert_eq!(outbox.resend(1), Ok(1));
+        assert_eq!(deliver(&mut outbox), [2, 3, 4, 5]);
+        assert_eq!(outbox.resend(6), Err(CommError::InvalidOperation));
+    }
+
+    #[test]
+    fn sealed() {
+        let sealed = SealedMessage {
+            from: 1,
+            to: 2,
+            id: 3,
+            time: std::time::UNIX_EPOCH,
+            nonce: [0u8; crate::SEALED_NONCE_LEN],
+            ciphertext: vec![1, 2, 3],
+        };
+        let mut outbox = outbox(4, 1);
+        assert_eq!(outbox.push_sealed(sealed.clone()), 2);
+        outbox.next_delivery().unwrap();
+        assert_eq!(
+            outbox.next_delivery(),
+            Some(Comm::DeliverSealed {
+                seq: 2,
+                message: sealed
+            })
+        );
+        assert_eq!(outbox.ack_up_to(2), Ok(2));
+    }
+
+    #[test]
+    fn inbox() {
+        let mut inbox = Inbox::new(1);
+        assert_eq!(inbox.ack(), None);
+        assert_eq!(inbox.receive(1), Received::New);
+        assert_eq!(inbox.receive(2), Received::New);
+        assert_eq!(inbox.unacked(), 2);
+        assert_eq!(inbox.ack(), Some(Comm::AckUpTo { from: 1, seq: 2 }));
+        assert_eq!(inbox.ack(), None);
+
+        // Redelivered after reconnect.
+        assert_eq!(inbox.receive(2), Received::Dropped);
+        assert_eq!(inbox.receive(3), Received::New);
+        assert_eq!(inbox.ack(), Some(Comm::AckUpTo { from: 1, seq: 3 }));
+    }
+
+    #[test]
+    fn inbox_gap() {
+        let mut inbox = Inbox::new(1);
+        assert_eq!(inbox.receive(1), Received::New);
+        // Message 2 was lost, so 3 can't be acknowledged.
+        assert_eq!(
+            inbox.receive(3),
+            Received::Resend(Comm::Resend { from: 1, seq: 1 })
+        );
+        assert_eq!(inbox.receive(4), Received::Dropped);
+        assert_eq!(inbox.unacked(), 0);
+        assert_eq!(inbox.ack(), None);
+
+        assert_eq!(inbox.receive(2), Received::New);
+        assert_eq!(inbox.receive(3), Received::New);
+        // Another gap is reported again.
+        assert_eq!(
+            inbox.receive(5),
+            Received::Resend(Comm::Resend { from: 1, seq: 3 })
+        );
+    }
+
+    /// Delivers everything from `outbox` to `inbox` and returns contents of new messages.
+    fn deliver_all(outbox: &mut Outbox, inbox: &mut Inbox) -> Vec<String> {
+        let mut delivered = Vec::new();
+        match outbox.start() {
+            Comm::DeliveryStart { epoch, seq, .. } => inbox.start(epoch, seq),
+            other => panic!("{:?}", other),
+        }
+        while !outbox.is_empty() {
+            while let Some(Comm::Deliver { seq, message }) = outbox.next_delivery() {
+                match inbox.receive(seq) {
+                    Received::New => delivered.push(message.content().clone()),
+                    Received::Dropped => {}
+                    other => panic!("{:?}", other),
+                }
+            }
+            if let Some(Comm::AckUpTo { seq, .. }) = inbox.ack() {
+                outbox.ack_up_to(seq).unwrap();
+            }
+        }
+        delivered
+    }
+
+    #[test]
+    fn delivery_start() {
+        let mut outbox = outbox(4, 3);
+        assert_eq!(
+            outbox.start(),
+            Comm::DeliveryStart {
+                from: 1,
+                epoch: outbox.epoch(),
+                seq: 0
+            }
+        );
+        deliver(&mut outbox);
+        assert_eq!(outbox.ack_up_to(2), Ok(2));
+        // Messages in flight are delivered again.
+        match outbox.start() {
+            Comm::DeliveryStart { seq: 2, .. } => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(deliver(&mut outbox), [3]);
+        assert_ne!(outbox.epoch(), Outbox::new(1).epoch());
+    }
+
+    #[test]
+    fn client_restart() {
+        let mut outbox = outbox(4, 60);
+        let mut inbox = Inbox::new(1);
+        assert_eq!(deliver_all(&mut outbox, &mut inbox).len(), 60);
+        for i in 60..70 {
+            outbox.push(Message::new(i.to_string(), 1, 2));
+        }
+
+        // Client lost its inbox, so it starts from what server knows was acknowledged.
+        let mut inbox = Inbox::new(1);
+        let expected: Vec<_> = (60..70).map(|i| i.to_string()).collect();
+        assert_eq!(deliver_all(&mut outbox, &mut inbox), expected);
+    }
+
+    #[test]
+    fn server_restart() {
+        let mut inbox = Inbox::new(1);
+        assert_eq!(deliver_all(&mut outbox(4, 50), &mut inbox).len(), 50);
+
+        // Server lost its outbox, so numbering starts again with new epoch.
+        let mut outbox = outbox(4, 3);
+        assert_eq!(deliver_all(&mut outbox, &mut inbox), ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn lost_ack() {
+        let mut outbox = outbox(4, 4);
+        let mut inbox = Inbox::new(1);
+        match outbox.start() {
+            Comm::DeliveryStart { epoch, seq, .. } => inbox.start(epoch, seq),
+            other => panic!("{:?}", other),
+        }
+        for seq in deliver(&mut outbox) {
+            assert_eq!(inbox.receive(seq), Received::New);
+        }
+        // `AckUpTo` is lost when client disconnects, so messages are delivered again after
+        // reconnect, but they aren't shown again.
+        inbox.ack().unwrap();
+        outbox.push(Message::new("new".to_string(), 1, 2));
+        assert_eq!(deliver_all(&mut outbox, &mut inbox), ["new"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut outbox = outbox(4, 3);
+        let mut inbox = Inbox::new(1);
+        assert_eq!(deliver_all(&mut outbox, &mut inbox).len(), 3);
+        outbox.push(Message::new("new".to_string(), 1, 2));
+        outbox.next_delivery().unwrap();
+
+        // Stored outbox keeps numbering and delivers message in flight again.
+        let json = serde_json::to_string(&outbox).unwrap();
+        let mut outbox: Outbox = serde_json::from_str(&json).unwrap();
+        assert_eq!(outbox.in_flight(), 0);
+        let json = serde_json::to_string(&inbox).unwrap();
+        let mut inbox: Inbox = serde_json::from_str(&json).unwrap();
+        assert_eq!(deliver_all(&mut outbox, &mut inbox), ["new"]);
+    }
+
+    #[test]
+    fn outbox_and_inbox() {
+        let mut outbox = outbox(4, 10);
+        let mut inbox = Inbox::new(1);
+        let mut delivered = Vec::new();
+
+        while !outbox.is_empty() {
+            while let Some(Comm::Deliver { seq, message }) = outbox.next_delivery() {
+                if inbox.receive(seq) == Received::New {
+                    delivered.push(message.content().clone());
+                }
+            }
+            if let Some(Comm::AckUpTo { seq, .. }) = inbox.ack() {
+                outbox.ack_up_to(seq).unwrap();
+            }
+        }
+
+        let expected: Vec<_> = (0..10).map(|i| i.to_string()).collect();
+        assert_eq!(delivered, expected);
+    }
+
+    #[test]
+    fn lost_delivery() {
+        let mut outbox = outbox(4, 6);
+        let mut inbox = Inbox::new(1);
+        let mut delivered = Vec::new();
+        let mut lost = false;
+
+        while !outbox.is_empty() {
+            while let Some(Comm::Deliver { seq, message }) = outbox.next_delivery() {
+                // Message 2 is lost the first time.
+                if seq == 2 && !lost {
+                    lost = true;
+                    continue;
+                }
+                match inbox.receive(seq) {
+                    Received::New => delivered.push(message.content().clone()),
+                    Received::Dropped => {}
+                    Received::Resend(Comm::Resend { seq, .. }) => {
+                        outbox.resend(seq).unwrap();
+                    }
+                    Received::Resend(other) => panic!("{:?}", other),
+                }
+            }
+            if let Some(Comm::AckUpTo { seq, .. }) = inbox.ack() {
+                outbox.ack_up_to(seq).unwrap();
+            }
+        }
+
+        let expected: Vec<_> = (0..6).map(|i| i.to_string()).collect();
+        assert_eq!(delivered, expected);
+    }
+}
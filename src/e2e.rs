@@ -0,0 +1,303 @@
+use crate::{Message, PublicKey, SealedMessage, PUBLIC_KEY_LEN, SEALED_NONCE_LEN};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key,
+};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use std::{error::Error, fmt};
+use x25519_dalek::StaticSecret;
+
+/// Errors that can occur when message is sealed or opened.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum E2eError {
+    /// Public key of peer is weak and gives shared secret known to everybody.
+    InvalidKey,
+
+    /// Ciphertext or message header was changed, or message wasn't sealed for this key pair.
+    Tampered,
+
+    /// Authentic content isn't valid UTF-8.
+    InvalidContent,
+}
+
+impl fmt::Display for E2eError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            E2eError::InvalidKey => write!(f, "invalid public key"),
+            E2eError::Tampered => write!(f, "message was tampered with or sealed for other key"),
+            E2eError::InvalidContent => write!(f, "message content is not valid UTF-8"),
+        }
+    }
+}
+
+impl Error for E2eError {}
+
+/// Returns fingerprint of public `key` as 30 digits in groups of five, so user can compare it
+/// with peer over other channel.
+pub fn fingerprint(key: &PublicKey) -> String {
+    let hash = Sha256::new()
+        .chain_update(b"talk-common fingerprint")
+        .chain_update(key)
+        .finalize();
+    hash[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns safety number of conversation between owners of keys `a` and `b`. It is the same for
+/// both sides, so users can compare it to check that server didn't replace their keys.
+pub fn safety_number(a: &PublicKey, b: &PublicKey) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    format!("{} {}", fingerprint(first), fingerprint(second))
+}
+
+/// X25519 key pair of user. Secret key never leaves client, public one is published with
+/// `Comm::PublishKey`.
+pub struct KeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates new random key pair.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn generate() -> Self {
+        let mut secret = [0u8; PUBLIC_KEY_LEN];
+        getrandom::getrandom(&mut secret).expect("Can't generate random secret key.");
+        Self::from_secret_bytes(secret)
+    }
+
+    /// Creates key pair from secret key returned by `secret_bytes`.
+    pub fn from_secret_bytes(bytes: [u8; PUBLIC_KEY_LEN]) -> Self {
+        let secret = StaticSecret::from(bytes);
+        let public = x25519_dalek::PublicKey::from(&secret).to_bytes();
+        Self { secret, public }
+    }
+
+    /// Returns secret key, so it can be stored by client.
+    pub fn secret_bytes(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.secret.to_bytes()
+    }
+
+    /// Returns public key.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public
+    }
+
+    /// Derives key shared by `self` and `peer`. Both directions use the same key, nonces are
+    /// random so they won't repeat.
+    fn cipher(&self, peer: &PublicKey) -> Result<ChaCha20Poly1305, E2eError> {
+        let shared = self
+            .secret
+            .diffie_hellman(&x25519_dalek::PublicKey::from(*peer));
+        if !shared.was_contributory() {
+            return Err(E2eError::InvalidKey);
+        }
+
+        let mut key = Key::default();
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(b"talk-common e2e message", &mut key)
+            .expect("32 bytes is valid HKDF output length.");
+        Ok(ChaCha20Poly1305::new(&key))
+    }
+}
+
+// Secret key is not printed, so it won't end in logs.
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SealedMessage {
+    /// Encrypts content of `message` so only owner of `recipient` key can read it. Sender,
+    /// recipient and time stay readable, but are authenticated with content.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes for nonce.
+    pub fn seal(
+        message: &Message,
+        sender: &KeyPair,
+        recipient: &PublicKey,
+    ) -> Result<Self, E2eError> {
+        let mut nonce = [0u8; SEALED_NONCE_LEN];
+        getrandom::getrandom(&mut nonce).expect("Can't generate random nonce.");
+        let mut sealed = Self {
+            from: *message.from(),
+            to: *message.to(),
+            id: message.id(),
+            time: *message.time(),
+            nonce,
+            ciphertext: Vec::new(),
+        };
+
+        let payload = Payload {
+            msg: message.content().as_bytes(),
+            aad: &sealed.header(),
+        };
+        sealed.ciphertext = sender
+            .cipher(recipient)?
+            .encrypt(&nonce.into(), payload)
+            .expect("Message content is too long to encrypt.");
+        Ok(sealed)
+    }
+
+    /// Decrypts message with `recipient` key pair and public key of `sender`. Returns
+    /// `E2eError::Tampered` if anything was changed on the way.
+    pub fn open(&self, recipient: &KeyPair, sender: &PublicKey) -> Result<Message, E2eError> {
+        let payload = Payload {
+            msg: &self.ciphertext,
+            aad: &self.header(),
+        };
+        let content = recipient
+            .cipher(sender)?
+            .decrypt(&self.nonce.into(), payload)
+            .map_err(|_| E2eError::Tampered)?;
+        let content = String::from_utf8(content).map_err(|_| E2eError::InvalidContent)?;
+
+        Ok(Message::with_id(
+            self.id, content, self.from, self.to, self.time,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::time_from_millis, SEALED_TAG_LEN};
+
+    fn alice_and_bob() -> (KeyPair, KeyPair, Message) {
+        let message = Message::new("Hi Bob".to_string(), 1, 2);
+        (KeyPair::generate(), KeyPair::generate(), message)
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let (alice, bob, message) = alice_and_bob();
+        let sealed = SealedMessage::seal(&message, &alice, bob.public_key()).unwrap();
+
+        assert_eq!(sealed.from(), message.from());
+        assert_eq!(sealed.to(), message.to());
+        assert_eq!(sealed.id(), message.id());
+        assert_eq!(sealed.time(), message.time());
+        assert_eq!(sealed.ciphertext().len(), 6 + SEALED_TAG_LEN);
+        assert_eq!(sealed.open(&bob, alice.public_key()).unwrap(), message);
+    }
+
+    #[test]
+    fn only_recipient_can_open() {
+        let (alice, bob, message) = alice_and_bob();
+        let eve = KeyPair::generate();
+        let sealed = SealedMessage::seal(&message, &alice, bob.public_key()).unwrap();
+
+        assert_eq!(
+            sealed.open(&eve, alice.public_key()),
+            Err(E2eError::Tampered)
+        );
+        // Sender must be the one who sealed message.
+        assert_eq!(sealed.open(&bob, eve.public_key()), Err(E2eError::Tampered));
+    }
+
+    #[test]
+    fn tampered_ciphertext() {
+        let (alice, bob, message) = alice_and_bob();
+        let sealed = SealedMessage::seal(&message, &alice, bob.public_key()).unwrap();
+
+        for i in 0..sealed.ciphertext.len() {
+            let mut tampered = sealed.clone();
+            tampered.ciphertext[i] ^= 1;
+            assert_eq!(
+                tampered.open(&bob, alice.public_key()),
+                Err(E2eError::Tampered)
+            );
+        }
+
+        let mut truncated = sealed.clone();
+        truncated.ciphertext.pop();
+        assert_eq!(
+            truncated.open(&bob, alice.public_key()),
+            Err(E2eError::Tampered)
+        );
+    }
+
+    #[test]
+    fn tampered_header() {
+        let (alice, bob, message) = alice_and_bob();
+        let sealed = SealedMessage::seal(&message, &alice, bob.public_key()).unwrap();
+
+        let mut redirected = sealed.clone();
+        redirected.to = 3;
+        let mut backdated = sealed.clone();
+        backdated.time = time_from_millis(0);
+        let mut reidentified = sealed.clone();
+        reidentified.id ^= 1;
+        let mut renonced = sealed;
+        renonced.nonce[0] ^= 1;
+
+        for tampered in [redirected, backdated, reidentified, renonced] {
+            assert_eq!(
+                tampered.open(&bob, alice.public_key()),
+                Err(E2eError::Tampered)
+            );
+        }
+    }
+
+    #[test]
+    fn weak_key() {
+        let (alice, _, message) = alice_and_bob();
+        assert_eq!(
+            SealedMessage::seal(&message, &alice, &[0u8; PUBLIC_KEY_LEN]),
+            Err(E2eError::InvalidKey)
+        );
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(E2eError::InvalidKey.to_string(), "invalid public key");
+        let e: Box<dyn Error> = Box::new(E2eError::InvalidContent);
+        assert_eq!(e.to_string(), "message content is not valid UTF-8");
+    }
+
+    #[test]
+    fn safety_numbers() {
+        let (alice, bob, _) = alice_and_bob();
+        let number = safety_number(alice.public_key(), bob.public_key());
+        assert_eq!(number, safety_number(bob.public_key(), alice.public_key()));
+        assert_eq!(number.len(), 12 * 5 + 11);
+        assert!(number
+            .split(' ')
+            .all(|group| group.len() == 5 && group.bytes().all(|b| b.is_ascii_digit())));
+
+        // Replaced key gives different number.
+        let eve = KeyPair::generate();
+        assert_ne!(number, safety_number(alice.public_key(), eve.public_key()));
+        assert_ne!(fingerprint(bob.public_key()), fingerprint(eve.public_key()));
+    }
+
+    #[test]
+    fn golden_fingerprint() {
+        // Fingerprints must never change, or users would see different numbers.
+        assert_eq!(
+            fingerprint(&[0u8; PUBLIC_KEY_LEN]),
+            "32423 64259 43122 93996 18744 76197"
+        );
+    }
+
+    #[test]
+    fn stored_key_pair() {
+        let alice = KeyPair::generate();
+        let loaded = KeyPair::from_secret_bytes(alice.secret_bytes());
+        assert_eq!(loaded.public_key(), alice.public_key());
+        assert!(!format!("{:?}", alice).contains("secret"));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    serialize::{Serialize, SerializeError},
+    ServerLimits,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+};
+
+/// Size in bytes of length prefix written before every frame.
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+/// Errors that can occur when frames are read from or written to stream.
+#[derive(Debug)]
+pub enum FrameError {
+    /// Underlying stream returned an error. Stream closed in the middle of frame is reported as
+    /// `ErrorKind::UnexpectedEof`.
+    Io(io::Error),
+
+    /// Frame is bigger than maximum allowed size. Contains frame length and maximum length.
+    TooLarge(usize, usize),
+
+    /// Frame was read but its payload couldn't be deserialized.
+    Serialize(SerializeError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "stream error: {}", e),
+            FrameError::TooLarge(len, max) => {
+                write!(f, "frame of {} bytes is bigger than {} bytes", len, max)
+            }
+            FrameError::Serialize(e) => write!(f, "invalid frame payload: {}", e),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(e) => Some(e),
+            FrameError::TooLarge(..) => None,
+            FrameError::Serialize(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+impl From<SerializeError> for FrameError {
+    fn from(err: SerializeError) -> Self {
+        FrameError::Serialize(err)
+    }
+}
+
+/// Writes length prefixed frames to any `Write` stream. Every frame starts with its payload length
+/// written as `u32` in little endian order followed by payload itself.
+pub struct FrameWriter<W: Write> {
+    inner: W,
+    max_frame_len: usize,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Creates new writer that accepts frames up to default `ServerLimits::max_frame_len` bytes
+    /// long.
+    pub fn new(inner: W) -> Self {
+        Self::with_max_frame_len(inner, ServerLimits::default().frame_len())
+    }
+
+    /// Creates new writer that accepts frames up to `max_frame_len` bytes long.
+    pub fn with_max_frame_len(inner: W, max_frame_len: usize) -> Self {
+        Self {
+            inner,
+            max_frame_len,
+        }
+    }
+
+    /// Returns maximum frame length in bytes.
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+
+    /// Returns reference to underlying stream.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns mutable reference to underlying stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes writer and returns underlying stream.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Changes maximum frame length in bytes.
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    /// Writes `payload` as single frame and flushes stream.
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), FrameError> {
+        write_frame(&mut self.inner, payload, self.max_frame_len)
+    }
+
+    /// Serializes `item` and writes it as single frame of `encoded_len` bytes.
+    pub fn write<T: Serialize>(&mut self, item: &T) -> Result<(), FrameError> {
+        self.write_frame(&item.serialize_to_vec()?)
+    }
+}
+
+/// Writes `payload` to `inner` as single frame and flushes it.
+pub(crate) fn write_frame<W: Write>(
+    inner: &mut W,
+    payload: &[u8],
+    max_frame_len: usize,
+) -> Result<(), FrameError> {
+    if payload.len() > max_frame_len || payload.len() > u32::MAX as usize {
+        return Err(FrameError::TooLarge(payload.len(), max_frame_len));
+    }
+
+    inner.write_all(&(payload.len() as u32).to_le_bytes())?;
+    inner.write_all(payload)?;
+    inner.flush()?;
+    Ok(())
+}
+
+/// Reads length prefixed frames written by `FrameWriter` from any `Read` stream. It keeps bytes
+/// that were read after the end of frame, so single read can contain many frames and frame can be
+/// split between many reads.
+pub struct FrameReader<R: Read> {
+    inner: R,
+    max_frame_len: usize,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Creates new reader that accepts frames up to default `ServerLimits::max_frame_len` bytes
+    /// long.
+    pub fn new(inner: R) -> Self {
+        Self::with_max_frame_len(inner, ServerLimits::default().frame_len())
+    }
+
+    /// Creates new reader that accepts frames up to `max_frame_len` bytes long.
+    pub fn with_max_frame_len(inner: R, max_frame_len: usize) -> Self {
+        Self {
+            inner,
+            max_frame_len,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Returns maximum frame length in bytes.
+    pub fn max_frame_len(&self) -> usize {
+        self.max_frame_len
+    }
+
+    /// Changes maximum frame length in bytes.
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    /// Returns reference to underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns mutable reference to underlying stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consumes reader and returns underlying stream. Bytes that were already read but not returned
+    /// as frame are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads next frame payload. Returns `None` when stream was closed between frames.
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(Some(frame));
+            }
+
+            let mut chunk = [0u8; crate::NET_BUFF_SIZE];
+            match self.inner.read(&mut chunk) {
+                Ok(0) if self.buffer.is_empty() => return Ok(None),
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Reads next frame and deserializes it. Returns `None` when stream was closed between frames
+    /// and `SerializeError::TrailingData` if frame contains anything after the item.
+    pub fn read<T: Serialize>(&mut self) -> Result<Option<T>, FrameError> {
+        match self.read_frame()? {
+            Some(frame) => Ok(Some(T::deserialize_exact(&frame)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes complete frame from internal buffer if there is one.
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header.copy_from_slice(&self.buffer[..FRAME_HEADER_SIZE]);
+        let len = u32::from_le_bytes(header) as usize;
+        if len > self.max_frame_len {
+            return Err(FrameError::TooLarge(len, self.max_frame_len));
+        }
+
+        let end = FRAME_HEADER_SIZE + len;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[FRAME_HEADER_SIZE..end].to_vec();
+        self.buffer.drain(..end);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Comm;
+
+    /// Reader that returns at most `step` bytes on every read.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        step: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn frames(payloads: &[&[u8]]) -> Vec<u8> {
+        let mut writer = FrameWriter::new(Vec::new());
+        for payload in payloads {
+            writer.write_frame(payload).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    #[test]
+    fn many_frames_in_one_read() {
+        let data = frames(&[b"abc", b"", b"defgh"]);
+        let mut reader = FrameReader::new(data.as_slice());
+
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"abc");
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"");
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"defgh");
+        assert!(reader.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn partial_reads() {
+        let data = frames(&[b"Hello", b"World"]);
+        let mut reader = FrameReader::new(Trickle {
+            data,
+            pos: 0,
+            step: 1,
+        });
+
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"Hello");
+        assert_eq!(reader.read_frame().unwrap().unwrap(), b"World");
+        assert!(reader.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn closed_in_middle_of_frame() {
+        let mut data = frames(&[b"Hello"]);
+        data.pop();
+        let mut reader = FrameReader::new(data.as_slice());
+
+        match reader.read_frame() {
+            Err(FrameError::Io(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut writer = FrameWriter::with_max_frame_len(Vec::new(), 4);
+        match writer.write_frame(b"12345") {
+            Err(FrameError::TooLarge(5, 4)) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(writer.get_ref().is_empty());
+
+        let data = frames(&[b"12345"]);
+        let mut reader = FrameReader::with_max_frame_len(data.as_slice(), 4);
+        match reader.read_frame() {
+            Err(FrameError::TooLarge(5, 4)) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn comm_round_trip() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write(&Comm::Disconnected(7)).unwrap();
+        writer.write(&Comm::AddFriend(3)).unwrap();
+
+        let data = writer.into_inner();
+        let mut reader = FrameReader::new(Trickle {
+            data,
+            pos: 0,
+            step: 100,
+        });
+        assert_eq!(reader.read::<Comm>().unwrap(), Some(Comm::Disconnected(7)));
+        assert_eq!(reader.read::<Comm>().unwrap(), Some(Comm::AddFriend(3)));
+        assert_eq!(reader.read::<Comm>().unwrap(), None);
+    }
+
+    #[test]
+    fn frame_not_padded() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write(&Comm::Accepted).unwrap();
+        assert_eq!(writer.into_inner(), [1, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn trailing_data() {
+        let data = frames(&[&[3, 0]]);
+        let mut reader = FrameReader::new(&data[..]);
+        match reader.read::<Comm>() {
+            Err(FrameError::Serialize(SerializeError::TrailingData { offset, .. })) => {
+                assert_eq!(offset, 1)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            FrameError::TooLarge(9, 8).to_string(),
+            "frame of 9 bytes is bigger than 8 bytes"
+        );
+        let e: Box<dyn Error> = Box::new(FrameError::Serialize(SerializeError::NotEnoughData));
+        assert_eq!(e.to_string(), "invalid frame payload: not enough data");
+        assert!(e.source().is_some());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::Comm;
+use std::time::{Duration, Instant};
+
+/// Default time without any incoming data after which `Comm::Ping` is sent.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Default time without any incoming data after which peer is considered dead.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Source of current time. `Heartbeat` uses it instead of `Instant::now`, so tests can control
+/// time.
+pub trait Clock {
+    /// Returns current time.
+    fn now(&self) -> Instant;
+}
+
+/// Clock that returns `Instant::now`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock> Clock for &C {
+    fn now(&self) -> Instant {
+        (*self).now()
+    }
+}
+
+/// What connection owner should do next, returned by `Heartbeat::poll`.
+#[derive(Debug, PartialEq)]
+pub enum HeartbeatAction {
+    /// Nothing to do for given time, unless something is received earlier.
+    Wait(Duration),
+
+    /// Send this `Comm::Ping` to peer.
+    Ping(Comm),
+
+    /// Peer didn't send anything for idle timeout. Connection should be closed and user marked as
+    /// logged out.
+    PeerDead,
+}
+
+/// Decides when to ping peer and when to declare it dead. Both sides of connection can use it.
+/// Every received `Comm` counts as sign of life, so ping is sent only on idle connection.
+#[derive(Debug)]
+pub struct Heartbeat<C: Clock = SystemClock> {
+    clock: C,
+    interval: Duration,
+    timeout: Duration,
+    last_received: Instant,
+    outstanding: Option<u64>,
+    next_nonce: u64,
+}
+
+impl Heartbeat {
+    /// Creates heartbeat that uses system clock. See `with_clock`.
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Self::with_clock(interval, timeout, SystemClock)
+    }
+}
+
+impl Default for Heartbeat {
+    /// Creates heartbeat with `DEFAULT_PING_INTERVAL` and `DEFAULT_IDLE_TIMEOUT`.
+    fn default() -> Self {
+        Self::new(DEFAULT_PING_INTERVAL, DEFAULT_IDLE_TIMEOUT)
+    }
+}
+
+impl<C: Clock> Heartbeat<C> {
+    /// Creates heartbeat that pings peer after `interval` without incoming data and declares it
+    /// dead after `timeout` without incoming data. `timeout` should be longer than `interval`,
+    /// so peer has time to answer. Connection is treated as alive at the moment of creation.
+    pub fn with_clock(interval: Duration, timeout: Duration, clock: C) -> Self {
+        Self {
+            last_received: clock.now(),
+            clock,
+            interval,
+            timeout,
+            outstanding: None,
+            next_nonce: 0,
+        }
+    }
+
+    /// Returns time without incoming data after which ping is sent.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns time without incoming data after which peer is dead.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Must be called for every received `Comm`. Returns `Comm::Pong` that must be sent back if
+    /// `comm` is `Comm::Ping`.
+    pub fn on_incoming(&mut self, comm: &Comm) -> Option<Comm> {
+        self.last_received = self.clock.now();
+        match comm {
+            Comm::Ping(nonce) => Some(Comm::Pong(*nonce)),
+            Comm::Pong(nonce) if self.outstanding == Some(*nonce) => {
+                self.outstanding = None;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if ping was sent and its pong wasn't received yet.
+    pub fn is_waiting_for_pong(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// Decides what to do now. It should be called again after returned `Wait` time, after
+    /// sending ping and after anything is received.
+    pub fn poll(&mut self) -> HeartbeatAction {
+        let idle = self
+            .clock
+            .now()
+            .saturating_duration_since(self.last_received);
+        if idle >= self.timeout {
+            return HeartbeatAction::PeerDead;
+        }
+
+        if self.outstanding.is_none() {
+            if idle >= self.interval {
+                let nonce = self.next_nonce;
+                self.next_nonce = self.next_nonce.wrapping_add(1);
+                self.outstanding = Some(nonce);
+                return HeartbeatAction::Ping(Comm::Ping(nonce));
+            }
+            HeartbeatAction::Wait((self.interval - idle).min(self.timeout - idle))
+        } else {
+            HeartbeatAction::Wait(self.timeout - idle)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Clock that moves only when test tells it to.
+    struct ManualClock(Cell<Instant>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Cell::new(Instant::now()))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn ping_when_idle() {
+        let clock = ManualClock::new();
+        let mut heartbeat = Heartbeat::with_clock(INTERVAL, TIMEOUT, &clock);
+
+        assert_eq!(heartbeat.poll(), HeartbeatAction::Wait(INTERVAL));
+        clock.advance(INTERVAL - Duration::from_secs(1));
+        assert_eq!(
+            heartbeat.poll(),
+            HeartbeatAction::Wait(Duration::from_secs(1))
+        );
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(heartbeat.poll(), HeartbeatAction::Ping(Comm::Ping(0)));
+        assert!(heartbeat.is_waiting_for_pong());
+        // Only one ping at a time.
+        assert_eq!(heartbeat.poll(), HeartbeatAction::Wait(TIMEOUT - INTERVAL));
+    }
+
+    #[test]
+    fn pong_keeps_alive() {
+        let clock = ManualClock::new();
+        let mut heartbeat = Heartbeat::with_clock(INTERVAL, TIMEOUT, &clock);
+
+        for nonce in 0..5 {
+            clock.advance(INTERVAL);
+            assert_eq!(heartbeat.poll(), HeartbeatAction::Ping(Comm::Ping(nonce)));
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(heartbeat.on_incoming(&Comm::Pong(nonce)), None);
+            assert!(!heartbeat.is_waiting_for_pong());
+        }
+        assert_eq!(heartbeat.poll(), HeartbeatAction::Wait(INTERVAL));
+    }
+
+    #[test]
+    fn traffic_keeps_alive() {
+        let clock = ManualClock::new();
+        let mut heartbeat = Heartbeat::with_clock(INTERVAL, TIMEOUT, &clock);
+
+        for _ in 0..10 {
+            clock.advance(INTERVAL - Duration::from_secs(1));
+            heartbeat.on_incoming(&Comm::Accepted);
+            assert!(matches!(heartbeat.poll(), HeartbeatAction::Wait(_)));
+        }
+    }
+
+    #[test]
+    fn peer_dead() {
+        let clock = ManualClock::new();
+        let mut heartbeat = Heartbeat::with_clock(INTERVAL, TIMEOUT, &clock);
+
+        clock.advance(INTERVAL);
+        assert_eq!(heartbeat.poll(), HeartbeatAction::Ping(Comm::Ping(0)));
+        // Pong with wrong nonce still proves that peer is alive, but doesn't answer ping.
+        heartbeat.on_incoming(&Comm::Pong(7));
+        assert!(heartbeat.is_waiting_for_pong());
+
+        clock.advance(TIMEOUT - Duration::from_millis(1));
+        assert_eq!(
+            heartbeat.poll(),
+            HeartbeatAction::Wait(Duration::from_millis(1))
+        );
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(heartbeat.poll(), HeartbeatAction::PeerDead);
+    }
+
+    #[test]
+    fn answer_ping() {
+        let mut heartbeat = Heartbeat::default();
+        assert_eq!(heartbeat.on_incoming(&Comm::Ping(42)), Some(Comm::Pong(42)));
+    }
+}
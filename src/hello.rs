@@ -0,0 +1,206 @@
+use std::convert::TryInto;
+
+use crate::{
+    serialize::{Serialize, SerializeError},
+    CommError,
+};
+
+/// Protocol revision number. It must be increased every time wire format changes.
+pub type ProtocolVersion = u16;
+
+/// Set of optional features as bit flags, e.g. `CAP_E2E`. Peers can use only features that both
+/// of them support.
+pub type Capabilities = u32;
+
+/// Peer can create and open `SealedMessage`, so other users can send it end-to-end encrypted
+/// messages. Server sets it if it routes `Comm::SealedMessage`.
+pub const CAP_E2E: Capabilities = 1;
+
+/// Capabilities that this build of crate supports. It has `CAP_E2E` only with `e2e` feature.
+pub const SUPPORTED_CAPABILITIES: Capabilities = if cfg!(feature = "e2e") { CAP_E2E } else { 0 };
+
+/// Newest protocol version known to this crate.
+pub const PROTOCOL_VERSION: ProtocolVersion = 9;
+
+/// Oldest protocol version this crate can still talk with. Crate has only one encoder and decoder
+/// of `Comm`, so it is the same as `PROTOCOL_VERSION`. Older versions were development revisions
+/// that were never released: they sent passwords or reused login proofs, so supporting them would
+/// bring back what later versions removed. Once current version is released, next change of wire
+/// format must keep decoding it and leave this constant unchanged, so older clients can still
+/// talk with newer servers.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 9;
+
+/// First message exchanged after connecting. It describes range of protocol versions and features
+/// supported by sender.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hello {
+    min_version: ProtocolVersion,
+    max_version: ProtocolVersion,
+    capabilities: Capabilities,
+}
+
+impl Hello {
+    // min_version = 2, max_version = 2 and capabilities = 4.
+    const BYTE_LEN: usize = 8;
+
+    /// Creates Hello describing versions supported by this crate.
+    pub fn new(capabilities: Capabilities) -> Self {
+        Self::with_versions(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, capabilities)
+    }
+
+    /// Creates Hello with custom range of supported versions.
+    pub fn with_versions(
+        min_version: ProtocolVersion,
+        max_version: ProtocolVersion,
+        capabilities: Capabilities,
+    ) -> Self {
+        Self {
+            min_version,
+            max_version,
+            capabilities,
+        }
+    }
+
+    /// Oldest supported protocol version.
+    pub fn min_version(&self) -> ProtocolVersion {
+        self.min_version
+    }
+
+    /// Newest supported protocol version.
+    pub fn max_version(&self) -> ProtocolVersion {
+        self.max_version
+    }
+
+    /// Supported features.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Picks highest protocol version supported by both `self` and `peer` and features that
+    /// both of them have. Returns `CommError::VersionMismatch` if version ranges don't overlap.
+    pub fn negotiate(&self, peer: &Hello) -> Result<(ProtocolVersion, Capabilities), CommError> {
+        let version = self.max_version.min(peer.max_version);
+        if version < self.min_version.max(peer.min_version) {
+            Err(CommError::VersionMismatch)
+        } else {
+            Ok((version, self.capabilities & peer.capabilities))
+        }
+    }
+}
+
+impl Serialize for Hello {
+    fn encoded_len(&self) -> usize {
+        Hello::BYTE_LEN
+    }
+
+    /// Writes minimum and maximum version followed by capabilities.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if buffer.len() < Hello::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let mut index = crate::write_bytes_to_buffer(buffer, &self.min_version.to_le_bytes())?;
+        index +=
+            crate::write_bytes_to_buffer(&mut buffer[index..], &self.max_version.to_le_bytes())?;
+        index +=
+            crate::write_bytes_to_buffer(&mut buffer[index..], &self.capabilities.to_le_bytes())?;
+
+        Ok(index)
+    }
+
+    /// Reads Hello written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < Hello::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let hello = Self {
+            min_version: u16::from_le_bytes(buffer[0..2].try_into().unwrap()),
+            max_version: u16::from_le_bytes(buffer[2..4].try_into().unwrap()),
+            capabilities: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+        };
+        Ok((hello, Hello::BYTE_LEN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highest_common_version() {
+        let server = Hello::with_versions(1, 5, 0b0111);
+        let client = Hello::with_versions(2, 3, 0b1101);
+
+        assert_eq!(server.negotiate(&client), Ok((3, 0b0101)));
+        assert_eq!(client.negotiate(&server), Ok((3, 0b0101)));
+    }
+
+    #[test]
+    fn newer_client() {
+        let server = Hello::with_versions(1, 2, 0);
+        let client = Hello::with_versions(2, 4, 0);
+
+        assert_eq!(server.negotiate(&client), Ok((2, 0)));
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let server = Hello::with_versions(3, 4, 0);
+        let client = Hello::with_versions(1, 2, 0);
+
+        assert_eq!(server.negotiate(&client), Err(CommError::VersionMismatch));
+        assert_eq!(client.negotiate(&server), Err(CommError::VersionMismatch));
+    }
+
+    #[test]
+    fn capabilities() {
+        let server = Hello::new(CAP_E2E);
+        assert_eq!(
+            server.negotiate(&Hello::new(CAP_E2E)),
+            Ok((PROTOCOL_VERSION, CAP_E2E))
+        );
+        assert_eq!(server.negotiate(&Hello::new(0)), Ok((PROTOCOL_VERSION, 0)));
+        assert_eq!(SUPPORTED_CAPABILITIES & CAP_E2E != 0, cfg!(feature = "e2e"));
+    }
+
+    #[test]
+    fn older_wire_format() {
+        // Older peers use wire format that this crate can't read anymore.
+        let old = Hello::with_versions(1, PROTOCOL_VERSION - 1, 0);
+        assert_eq!(
+            Hello::new(0).negotiate(&old),
+            Err(CommError::VersionMismatch)
+        );
+        assert_eq!(
+            Hello::new(0).negotiate(&Hello::new(0)),
+            Ok((PROTOCOL_VERSION, 0))
+        );
+    }
+
+    #[test]
+    fn send_and_recive() {
+        let hello = Hello::with_versions(1, 0x0203, 0x0A0B_0C0D);
+        let mut buffer = [0u8; Hello::BYTE_LEN];
+        hello.serialize(&mut buffer).unwrap();
+
+        assert_eq!(buffer, [1, 0, 3, 2, 0x0D, 0x0C, 0x0B, 0x0A]);
+        assert_eq!(Hello::deserialize(&buffer).unwrap().0, hello);
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        let hello = Hello::new(0);
+        for short in 0..Hello::BYTE_LEN {
+            assert_eq!(
+                hello.serialize(&mut vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+            assert_eq!(
+                Hello::deserialize(&vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+    }
+}
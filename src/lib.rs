@@ -1,17 +1,66 @@
 //! Defines data types and constants that are used by `talk-client` and `talk-server` It
 //! doesn't do anything on it's own.
-
+//!
+//! All integers (`UserID`, counts, lengths and timestamps) are sent over the network in little
+//! endian byte order, no matter what is native byte order of machine that sends them.
+
+mod account;
+mod auth;
+#[cfg(feature = "tokio")]
+mod codec;
 #[warn(missing_docs)]
 mod comm;
+mod connection;
+mod delivery;
+#[cfg(feature = "e2e")]
+mod e2e;
+mod frame;
+mod heartbeat;
+mod hello;
+mod limits;
 mod message;
+mod policy;
+mod queue;
+mod request;
+mod reservation;
+mod sealed;
 mod serialize;
+mod session;
+mod state;
 mod user;
 
+pub use account::{Account, MAX_FAILED_LOGINS};
+pub use auth::{
+    login_proof, new_nonce, AuthParams, AuthVerifier, Challenge, Nonce, PasswordChange, Proof,
+    AUTH_ITERATIONS, AUTH_SALT_LEN, MAX_AUTH_ITERATIONS, NONCE_LEN, PROOF_LEN,
+};
+#[cfg(feature = "tokio")]
+pub use codec::TalkCodec;
 pub use comm::{Comm, CommError};
-pub use message::Message;
+pub use connection::{Connection, Socket};
+pub use delivery::{Inbox, Outbox, Pending, Received, SeqNum, DEFAULT_DELIVERY_WINDOW};
+#[cfg(feature = "e2e")]
+pub use e2e::{fingerprint, safety_number, E2eError, KeyPair};
+pub use frame::{FrameError, FrameReader, FrameWriter, FRAME_HEADER_SIZE};
+pub use heartbeat::{
+    Clock, Heartbeat, HeartbeatAction, SystemClock, DEFAULT_IDLE_TIMEOUT, DEFAULT_PING_INTERVAL,
+};
+pub use hello::{
+    Capabilities, Hello, ProtocolVersion, CAP_E2E, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    SUPPORTED_CAPABILITIES,
+};
+pub use limits::ServerLimits;
+pub use message::{new_message_id, Message, MessageId};
+pub use policy::{PasswordError, PasswordPolicy};
+pub use queue::{MessageQueue, DEFAULT_LEASE_TIMEOUT, DEFAULT_QUEUE_CAPACITY, SEEN_IDS_PER_SENDER};
+pub use request::{PendingRequests, RequestId, DEFAULT_REQUEST_TIMEOUT};
+pub use reservation::{IdReservation, RESERVATION_TTL};
+pub use sealed::{PublicKey, SealedMessage, PUBLIC_KEY_LEN, SEALED_NONCE_LEN, SEALED_TAG_LEN};
 pub use serialize::{Serialize, SerializeError};
+pub use session::{Session, SessionToken, SESSION_TOKEN_LEN, SESSION_TTL};
+pub use state::{ProtocolPhase, ProtocolState, Role};
 use std::{convert::TryInto, mem, str};
-pub use user::User;
+pub use user::UserProfile;
 
 // NOTE: I've created separate type in case we want to change it for something more advanced in the
 // future.
@@ -20,60 +69,91 @@ pub type UserID = u64;
 /// Size in bytes of UserID
 pub const USER_ID_SIZE: usize = mem::size_of::<UserID>();
 
-/// Port used for communication between client and server.
+/// Port used for communication between client and server. It can't be advertised by server, so
+/// it is only default that can be overridden by client and server configuration.
 pub const COMM_PORT: u16 = 7878; // 7878 is Rust typed on phone keybord
 
 /// Size of network buffer in bytes.
 pub const NET_BUFF_SIZE: usize = 512;
 
 /// Minimum user password length in characters, not bytes. Unicode characters can take more than
-/// one byte of memory.
+/// one byte of memory. Default value of `ServerLimits::min_pass_char_len`.
 pub const MIN_PASS_CHAR_LEN: usize = 4;
 
 /// Maximum user password length in bytes, not characters. Unicode characters can take more than
 /// one byte of memory. We are using bytes here instead of characters because we must know how many
-/// bytes it will take to save/load to/from file or transfer thru network.
+/// bytes it will take to save/load to/from file or transfer thru network. Default value of
+/// `ServerLimits::max_pass_byte_len`.
 pub const MAX_PASS_BYTE_LEN: usize = 30;
 
-/// Maximum message length in bytes, not characters.
+/// Maximum message length in bytes, not characters. Default value of
+/// `ServerLimits::max_message_byte_len`.
 pub const MAX_MESSAGE_BYTE_LEN: usize = 128;
 
-/// Returns UserID from a slice of bytes.
-///
-/// # Panics
-/// This function will panic if slice is shorter than `USER_ID_SIZE`.
-// TODO: This function should propably return Result in case of parsing error.
-pub fn parse_id_from_bytes(bytes: &[u8]) -> UserID {
-    u64::from_ne_bytes(bytes[..USER_ID_SIZE].try_into().unwrap())
+/// Returns UserID from a slice of little endian bytes or `SerializeError::NotEnoughData` if slice is shorter
+/// than `USER_ID_SIZE`.
+pub fn parse_id_from_bytes(bytes: &[u8]) -> Result<UserID, SerializeError> {
+    match bytes.get(..USER_ID_SIZE) {
+        Some(id) => Ok(u64::from_le_bytes(id.try_into().unwrap())),
+        None => Err(SerializeError::NotEnoughData),
+    }
 }
 
-/// Returns string from a slice of null terminated bytes (similiar to those in C language)
-/// or empty slice on error.
-// TODO: This function should propably return Result in case of parsing error.
-pub fn parse_string_from_bytes(bytes: &[u8]) -> &str {
-    str::from_utf8(bytes.split(|&c| c == 0).next().unwrap_or_default()).unwrap_or_default()
+/// Returns string from a slice of null terminated bytes (similiar to those in C language). If
+/// there is no null byte whole slice is used. Returns `SerializeError::InvalidUtf8` if bytes are
+/// not valid UTF-8.
+pub fn parse_string_from_bytes(bytes: &[u8]) -> Result<&str, SerializeError> {
+    let bytes = bytes.split(|&c| c == 0).next().unwrap_or_default();
+    parse_utf8(bytes, "str", 0)
 }
 
-/// Writes bytes to buffer one by one, and returns number of bytes written.
-///
-/// # Panics
-/// This function will panic if buffer is not big enough to fit all bytes.
-pub fn write_bytes_to_buffer(buffer: &mut [u8], bytes: &[u8]) -> usize {
-    if buffer.len() < bytes.len() {
-        panic!(
-            "Buffer ({}) too small to fit all bytes ({}).",
-            buffer.len(),
-            bytes.len()
-        );
-    }
+/// Returns string from `bytes` that start at `offset` of `type_name`. Returns
+/// `SerializeError::InvalidUtf8` with offset of first invalid byte if bytes are not valid UTF-8.
+pub(crate) fn parse_utf8<'a>(
+    bytes: &'a [u8],
+    type_name: &'static str,
+    offset: usize,
+) -> Result<&'a str, SerializeError> {
+    str::from_utf8(bytes).map_err(|e| SerializeError::InvalidUtf8 {
+        type_name: type_name.into(),
+        offset: offset + e.valid_up_to(),
+    })
+}
 
-    let mut index = 0;
-    for byte in bytes {
-        buffer[index] = *byte;
-        index += 1;
+/// Writes bytes to buffer one by one, and returns number of bytes written or
+/// `SerializeError::NotEnoughData` if buffer is not big enough to fit all bytes.
+pub fn write_bytes_to_buffer(buffer: &mut [u8], bytes: &[u8]) -> Result<usize, SerializeError> {
+    match buffer.get_mut(..bytes.len()) {
+        Some(dest) => {
+            dest.copy_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        None => Err(SerializeError::NotEnoughData),
     }
+}
 
-    index
+/// Writes string to field of `field_len` bytes at the beginning of buffer. Rest of the field is
+/// filled with zeros. Returns `field_len` on success, `SerializeError::StringTooLong` if string
+/// doesn't fit in field or `SerializeError::NotEnoughData` if field doesn't fit in buffer.
+pub fn write_string_to_buffer(
+    buffer: &mut [u8],
+    string: &str,
+    field_len: usize,
+) -> Result<usize, SerializeError> {
+    let field = buffer
+        .get_mut(..field_len)
+        .ok_or(SerializeError::NotEnoughData)?;
+    if string.len() > field_len {
+        return Err(SerializeError::StringTooLong {
+            type_name: "str".into(),
+            offset: 0,
+            len: string.len(),
+            max: field_len,
+        });
+    }
+    let index = write_bytes_to_buffer(field, string.as_bytes())?;
+    field[index..].fill(0);
+    Ok(field_len)
 }
 
 #[cfg(test)]
@@ -81,12 +161,67 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::char_lit_as_u8)]
     fn string_from_bytes() {
         let bytes = [
             'H' as u8, 'e' as u8, 'l' as u8, 'l' as u8, 'o' as u8, 0, 'W' as u8, 'o' as u8,
         ];
 
-        let string = parse_string_from_bytes(&bytes);
+        let string = parse_string_from_bytes(&bytes).unwrap();
         assert_eq!(string, "Hello");
     }
+
+    #[test]
+    fn invalid_string_from_bytes() {
+        let bytes = [b'H', 0xFF, 0];
+        assert_eq!(
+            parse_string_from_bytes(&bytes),
+            Err(SerializeError::InvalidUtf8 {
+                type_name: "str".into(),
+                offset: 1
+            })
+        );
+    }
+
+    #[test]
+    fn id_from_short_slice() {
+        for len in 0..USER_ID_SIZE {
+            let bytes = vec![1u8; len];
+            assert_eq!(
+                parse_id_from_bytes(&bytes),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+        assert_eq!(parse_id_from_bytes(&[1, 0, 0, 0, 0, 0, 0, 0, 9]), Ok(1));
+    }
+
+    #[test]
+    fn bytes_to_small_buffer() {
+        let mut buffer = [0u8; 3];
+        assert_eq!(
+            write_bytes_to_buffer(&mut buffer, b"abcd"),
+            Err(SerializeError::NotEnoughData)
+        );
+        assert_eq!(write_bytes_to_buffer(&mut buffer, b"abc"), Ok(3));
+    }
+
+    #[test]
+    fn string_to_field() {
+        let mut buffer = [0xFFu8; 6];
+        assert_eq!(write_string_to_buffer(&mut buffer, "ab", 4), Ok(4));
+        assert_eq!(buffer, [b'a', b'b', 0, 0, 0xFF, 0xFF]);
+        assert_eq!(
+            write_string_to_buffer(&mut buffer, "abcde", 4),
+            Err(SerializeError::StringTooLong {
+                type_name: "str".into(),
+                offset: 0,
+                len: 5,
+                max: 4
+            })
+        );
+        assert_eq!(
+            write_string_to_buffer(&mut buffer, "ab", 7),
+            Err(SerializeError::NotEnoughData)
+        );
+    }
 }
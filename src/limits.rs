@@ -0,0 +1,382 @@
+use std::convert::TryInto;
+
+use crate::{
+    serialize::{Serialize, SerializeError},
+    CommError, Message, PasswordPolicy, SealedMessage, UserProfile, SEALED_TAG_LEN,
+};
+
+/// Limits that server sends to every client with `Comm::Limits` right after handshake. Both sides
+/// must use the same limits for serialization and validation. Default values are equal to crate
+/// constants, so client that didn't receive limits yet can still talk with server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerLimits {
+    /// Minimum user password length in characters, not bytes.
+    pub min_pass_char_len: u32,
+
+    /// Maximum user password length in bytes.
+    pub max_pass_byte_len: u32,
+
+    /// Maximum message length in bytes, not characters.
+    pub max_message_byte_len: u32,
+
+    /// Maximum size of single frame in bytes. It should be at least `required_frame_len`, or some
+    /// valid messages won't fit in frame.
+    pub max_frame_len: u32,
+}
+
+impl ServerLimits {
+    // Four u32 fields.
+    const BYTE_LEN: usize = 16;
+
+    // Request or Response tag = 1, request ID = 4 and tag of wrapped Comm = 1.
+    const WRAPPED_COMM_LEN: usize = 6;
+
+    /// Highest `max_pass_byte_len` accepted from server.
+    pub const MAX_PASS_BYTE_LEN: u32 = 1024;
+
+    /// Highest `max_message_byte_len` accepted from server. Longer content can't be described by
+    /// length field of `Message`.
+    pub const MAX_MESSAGE_BYTE_LEN: u32 = u16::MAX as u32;
+
+    /// Highest `max_frame_len` accepted from server.
+    pub const MAX_FRAME_LEN: u32 = 1 << 20;
+
+    /// Returns true if limits can be used: password length is between 1 and
+    /// `MAX_PASS_BYTE_LEN` and minimum isn't bigger than maximum, and message and frame lengths
+    /// are between 1 and `MAX_MESSAGE_BYTE_LEN` and `MAX_FRAME_LEN`.
+    pub fn is_valid(&self) -> bool {
+        self.invalid_field().is_none()
+    }
+
+    /// Returns offset of first field that breaks rules of `is_valid`.
+    fn invalid_field(&self) -> Option<usize> {
+        if self.min_pass_char_len == 0 || self.min_pass_char_len > self.max_pass_byte_len {
+            Some(0)
+        } else if self.max_pass_byte_len > ServerLimits::MAX_PASS_BYTE_LEN {
+            Some(4)
+        } else if !(1..=ServerLimits::MAX_MESSAGE_BYTE_LEN).contains(&self.max_message_byte_len) {
+            Some(8)
+        } else if !(1..=ServerLimits::MAX_FRAME_LEN).contains(&self.max_frame_len) {
+            Some(12)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `max_pass_byte_len` as `usize`.
+    pub fn max_pass_len(&self) -> usize {
+        self.max_pass_byte_len as usize
+    }
+
+    /// Returns `max_frame_len` as `usize`.
+    pub fn frame_len(&self) -> usize {
+        self.max_frame_len as usize
+    }
+
+    /// Returns size of largest frame that other limits allow. It is `Comm::User` with longest
+    /// name and most friends and invitations, or `Comm::DeliverSealed` with longest content, both
+    /// wrapped in `Comm::Response`. Default `max_frame_len` is equal to it.
+    pub fn required_frame_len(&self) -> usize {
+        let sealed =
+            8 + SealedMessage::MIN_BYTE_LEN + self.max_message_byte_len as usize + SEALED_TAG_LEN;
+        ServerLimits::WRAPPED_COMM_LEN + sealed.max(UserProfile::MAX_BYTE_LEN)
+    }
+
+    /// Returns policy that enforces password limits. Server never receives passwords, so only
+    /// client can enforce it, see `Comm::register` and `Comm::change_password`.
+    pub fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            min_char_len: self.min_pass_char_len as usize,
+            max_byte_len: self.max_pass_len(),
+        }
+    }
+
+    /// Checks if message content isn't longer than `max_message_byte_len` bytes. Returns
+    /// `CommError::MessageTooLong` otherwise. Use `validate_sealed` for `SealedMessage`.
+    pub fn validate_message(&self, message: &Message) -> Result<(), CommError> {
+        if message.content().len() > self.max_message_byte_len as usize {
+            Err(CommError::MessageTooLong)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks if sealed message content isn't longer than `max_message_byte_len` bytes. Content
+    /// is encrypted, so its length is length of ciphertext without authentication tag. Returns
+    /// `CommError::MessageTooLong` otherwise.
+    pub fn validate_sealed(&self, message: &SealedMessage) -> Result<(), CommError> {
+        let content_len = message.ciphertext().len().saturating_sub(SEALED_TAG_LEN);
+        if content_len > self.max_message_byte_len as usize {
+            Err(CommError::MessageTooLong)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for ServerLimits {
+    /// Uses crate constants and `required_frame_len` of them as `max_frame_len`.
+    fn default() -> Self {
+        let mut limits = Self {
+            min_pass_char_len: crate::MIN_PASS_CHAR_LEN as u32,
+            max_pass_byte_len: crate::MAX_PASS_BYTE_LEN as u32,
+            max_message_byte_len: crate::MAX_MESSAGE_BYTE_LEN as u32,
+            max_frame_len: 0,
+        };
+        limits.max_frame_len = limits.required_frame_len() as u32;
+        limits
+    }
+}
+
+impl Serialize for ServerLimits {
+    fn encoded_len(&self) -> usize {
+        ServerLimits::BYTE_LEN
+    }
+
+    /// Writes all limits in order of declaration. Returns `SerializeError::InvalidValue` if they
+    /// break rules of `ServerLimits::is_valid`, because client would reject them.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        if let Some(offset) = self.invalid_field() {
+            return Err(SerializeError::InvalidValue {
+                type_name: "ServerLimits".into(),
+                offset,
+            });
+        }
+        if buffer.len() < ServerLimits::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let mut index = 0;
+        for value in [
+            self.min_pass_char_len,
+            self.max_pass_byte_len,
+            self.max_message_byte_len,
+            self.max_frame_len,
+        ] {
+            index += crate::write_bytes_to_buffer(&mut buffer[index..], &value.to_le_bytes())?;
+        }
+
+        Ok(index)
+    }
+
+    /// Reads limits written by `serialize`. Returns `SerializeError::InvalidValue` if they break
+    /// rules of `ServerLimits::is_valid`, so malicious server can't make client use them.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < ServerLimits::BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let value = |index: usize| u32::from_le_bytes(buffer[index..index + 4].try_into().unwrap());
+        let limits = Self {
+            min_pass_char_len: value(0),
+            max_pass_byte_len: value(4),
+            max_message_byte_len: value(8),
+            max_frame_len: value(12),
+        };
+        if let Some(offset) = limits.invalid_field() {
+            return Err(SerializeError::InvalidValue {
+                type_name: "ServerLimits".into(),
+                offset,
+            });
+        }
+        Ok((limits, ServerLimits::BYTE_LEN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let limits = ServerLimits::default();
+        assert_eq!(limits.max_pass_len(), crate::MAX_PASS_BYTE_LEN);
+        assert_eq!(limits.frame_len(), limits.required_frame_len());
+        assert!(limits.is_valid());
+    }
+
+    #[test]
+    fn largest_profile_fits_default_frame() {
+        let mut user = UserProfile::new(1);
+        user.set_name("a".repeat(u8::MAX as usize));
+        for id in 0..u8::MAX as u64 {
+            user.add_friend(id);
+            user.add_invitation(id + 1000);
+        }
+        let response = crate::Comm::Response {
+            id: 1,
+            response: Box::new(crate::Comm::User(user)),
+        };
+
+        let len = response.serialize_to_vec().unwrap().len();
+        assert_eq!(
+            len,
+            ServerLimits::WRAPPED_COMM_LEN + UserProfile::MAX_BYTE_LEN
+        );
+        assert!(len <= ServerLimits::default().frame_len());
+    }
+
+    #[test]
+    fn largest_message_fits_frame() {
+        let limits = ServerLimits {
+            max_message_byte_len: ServerLimits::MAX_MESSAGE_BYTE_LEN,
+            ..Default::default()
+        };
+        let content = "a".repeat(limits.max_message_byte_len as usize);
+        let message = crate::Comm::Deliver {
+            seq: 1,
+            message: Message::new(content, 1, 2),
+        };
+        assert!(message.encoded_len() <= limits.required_frame_len());
+        assert!(limits.required_frame_len() <= ServerLimits::MAX_FRAME_LEN as usize);
+    }
+
+    #[test]
+    fn password_policy() {
+        let limits = ServerLimits {
+            min_pass_char_len: 2,
+            max_pass_byte_len: 4,
+            ..Default::default()
+        };
+        let policy = limits.password_policy();
+        assert_eq!(policy.min_char_len, 2);
+        assert_eq!(policy.max_byte_len, 4);
+    }
+
+    #[test]
+    fn message() {
+        let limits = ServerLimits {
+            max_message_byte_len: 2,
+            ..Default::default()
+        };
+
+        let message = Message::new("Hi".to_string(), 1, 2);
+        assert_eq!(limits.validate_message(&message), Ok(()));
+        let message = Message::new("Hi!".to_string(), 1, 2);
+        assert_eq!(
+            limits.validate_message(&message),
+            Err(CommError::MessageTooLong)
+        );
+    }
+
+    #[test]
+    fn sealed_message() {
+        let limits = ServerLimits {
+            max_message_byte_len: 2,
+            ..Default::default()
+        };
+        let sealed = |content_len| SealedMessage {
+            from: 1,
+            to: 2,
+            id: 3,
+            time: std::time::UNIX_EPOCH,
+            nonce: [0u8; crate::SEALED_NONCE_LEN],
+            ciphertext: vec![0u8; content_len + SEALED_TAG_LEN],
+        };
+
+        assert_eq!(limits.validate_sealed(&sealed(2)), Ok(()));
+        assert_eq!(
+            limits.validate_sealed(&sealed(3)),
+            Err(CommError::MessageTooLong)
+        );
+    }
+
+    #[test]
+    fn invalid_limits() {
+        assert!(ServerLimits::default().is_valid());
+        let invalid = [
+            (
+                ServerLimits {
+                    min_pass_char_len: 0,
+                    ..Default::default()
+                },
+                0,
+            ),
+            (
+                ServerLimits {
+                    min_pass_char_len: 5,
+                    max_pass_byte_len: 4,
+                    ..Default::default()
+                },
+                0,
+            ),
+            (
+                ServerLimits {
+                    max_pass_byte_len: u32::MAX,
+                    ..Default::default()
+                },
+                4,
+            ),
+            (
+                ServerLimits {
+                    max_message_byte_len: 0,
+                    ..Default::default()
+                },
+                8,
+            ),
+            (
+                ServerLimits {
+                    max_message_byte_len: ServerLimits::MAX_MESSAGE_BYTE_LEN + 1,
+                    ..Default::default()
+                },
+                8,
+            ),
+            (
+                ServerLimits {
+                    max_frame_len: 0,
+                    ..Default::default()
+                },
+                12,
+            ),
+            (
+                ServerLimits {
+                    max_frame_len: ServerLimits::MAX_FRAME_LEN + 1,
+                    ..Default::default()
+                },
+                12,
+            ),
+        ];
+
+        for (limits, offset) in invalid {
+            assert!(!limits.is_valid(), "{:?}", limits);
+            let err = SerializeError::InvalidValue {
+                type_name: "ServerLimits".into(),
+                offset,
+            };
+            assert_eq!(limits.serialize_to_vec(), Err(err.clone()));
+
+            let bytes: Vec<u8> = [
+                limits.min_pass_char_len,
+                limits.max_pass_byte_len,
+                limits.max_message_byte_len,
+                limits.max_frame_len,
+            ]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+            assert_eq!(ServerLimits::deserialize(&bytes), Err(err));
+        }
+    }
+
+    #[test]
+    fn send_and_recive() {
+        let limits = ServerLimits {
+            min_pass_char_len: 1,
+            max_pass_byte_len: 2,
+            max_message_byte_len: 3,
+            max_frame_len: 0x0405,
+        };
+        let mut buffer = [0u8; ServerLimits::BYTE_LEN];
+        limits.serialize(&mut buffer).unwrap();
+
+        assert_eq!(buffer, [1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 5, 4, 0, 0]);
+        assert_eq!(ServerLimits::deserialize(&buffer).unwrap().0, limits);
+        for short in 0..ServerLimits::BYTE_LEN {
+            assert_eq!(
+                ServerLimits::deserialize(&buffer[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+    }
+}
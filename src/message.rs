@@ -1,13 +1,33 @@
-use std::time::SystemTime;
+use std::{
+    convert::TryInto,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     serialize::{Serialize, SerializeError},
-    UserID,
+    UserID, USER_ID_SIZE,
 };
 
+/// Identifier of message generated by sender. It is random, so it is unique per sender without
+/// any coordination. Together with sender ID it identifies message, which lets receivers drop
+/// duplicates of resend messages.
+pub type MessageId = u64;
+
+/// Returns new random `MessageId`.
+///
+/// # Panics
+/// This function will panic if operating system can't provide random bytes.
+pub fn new_message_id() -> MessageId {
+    let mut id = [0u8; 8];
+    getrandom::getrandom(&mut id).expect("Can't generate random message ID.");
+    MessageId::from_le_bytes(id)
+}
+
 /// Represents message that can be sent between users.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
+    id: MessageId,
     from: UserID,
     to: UserID,
     // NOTE: In future we shuld have also Time Zone here.
@@ -16,16 +36,42 @@ pub struct Message {
 }
 
 impl Message {
-    /// Creates new message with current system time.
+    // from = 8, to = 8, id = 8, time = 8 and content length = 2.
+    const MIN_BYTE_LEN: usize = 2 * USER_ID_SIZE + 8 + 8 + 2;
+
+    /// Creates new message with random ID and current system time.
     pub fn new(content: String, from: UserID, to: UserID) -> Self {
+        Self::with_time(content, from, to, SystemTime::now())
+    }
+
+    /// Creates new message with random ID and given send time. Time is truncated to milliseconds
+    /// because this is the precision used on the wire.
+    pub fn with_time(content: String, from: UserID, to: UserID, time: SystemTime) -> Self {
+        Self::with_id(new_message_id(), content, from, to, time)
+    }
+
+    /// Creates message with given ID and send time. Message that is send again must keep its ID.
+    pub fn with_id(
+        id: MessageId,
+        content: String,
+        from: UserID,
+        to: UserID,
+        time: SystemTime,
+    ) -> Self {
         Self {
+            id,
             from,
             to,
-            time: SystemTime::now(),
+            time: time_from_millis(time_to_millis(&time)),
             content,
         }
     }
 
+    /// Returns message ID.
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
     /// Returns sender ID.
     pub fn from(&self) -> &UserID {
         &self.from
@@ -47,42 +93,86 @@ impl Message {
     }
 }
 
-impl Serialize for Message {
-    type Item = Message;
-
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), SerializeError> {
-        // TODO: Add buffer size checks and return appropirate SerializeError instead of panic from
-        // accessing index out of buffer slice. This is done this way for now as a MVP.
-        let mut index = crate::write_bytes_to_buffer(
-            &mut buffer[..crate::USER_ID_SIZE],
-            &self.from.to_ne_bytes(),
-        );
-        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.to.to_ne_bytes());
-
-        // TODO: Serialize SystemTime here.
+/// Returns number of milliseconds since Unix epoch. Times before epoch are treated as epoch.
+pub(crate) fn time_to_millis(time: &SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
 
-        crate::write_bytes_to_buffer(&mut buffer[index..], self.content.as_bytes());
+/// Returns time that is `millis` milliseconds after Unix epoch.
+pub(crate) fn time_from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
 
-        Ok(())
+impl Serialize for Message {
+    fn encoded_len(&self) -> usize {
+        Message::MIN_BYTE_LEN + self.content.len()
     }
 
-    fn deserialize(buffer: &[u8]) -> Result<Self::Item, SerializeError> {
-        // TODO: Add buffer size checks and return appropirate SerializeError instead of panic from
-        // accessing index out of buffer slice. This is done this way for now as a MVP.
-        let from = crate::parse_id_from_bytes(&buffer[..crate::USER_ID_SIZE]);
-        let index = 2 * crate::USER_ID_SIZE;
-        let to = crate::parse_id_from_bytes(&buffer[crate::USER_ID_SIZE..index]);
+    /// Writes sender and reciever IDs, message ID, send time as milliseconds since Unix epoch and content
+    /// preceded by its length in bytes.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        let content = self.content.as_bytes();
+        // Content longer than u16 can't be described by length field.
+        if content.len() > u16::MAX as usize {
+            return Err(SerializeError::StringTooLong {
+                type_name: "Message".into(),
+                offset: Message::MIN_BYTE_LEN,
+                len: content.len(),
+                max: u16::MAX as usize,
+            });
+        }
+        if buffer.len() < Message::MIN_BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
 
-        // TODO: Deserialize SystemTime here.
+        let mut index = crate::write_bytes_to_buffer(buffer, &self.from.to_le_bytes())?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.to.to_le_bytes())?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.id.to_le_bytes())?;
+        index += crate::write_bytes_to_buffer(
+            &mut buffer[index..],
+            &time_to_millis(&self.time).to_le_bytes(),
+        )?;
+        index += crate::write_bytes_to_buffer(
+            &mut buffer[index..],
+            &(content.len() as u16).to_le_bytes(),
+        )?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], content)?;
+
+        Ok(index)
+    }
 
-        let content = crate::parse_string_from_bytes(&buffer[index..]).to_string();
+    /// Reads message written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < Message::MIN_BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
 
-        Ok(Self {
+        let from = crate::parse_id_from_bytes(buffer)?;
+        let mut index = USER_ID_SIZE;
+        let to = crate::parse_id_from_bytes(&buffer[index..])?;
+        index += USER_ID_SIZE;
+        let id = u64::from_le_bytes(buffer[index..index + 8].try_into().unwrap());
+        index += 8;
+        let millis = u64::from_le_bytes(buffer[index..index + 8].try_into().unwrap());
+        index += 8;
+        let content_len = u16::from_le_bytes(buffer[index..index + 2].try_into().unwrap()) as usize;
+        index += 2;
+
+        let content = buffer
+            .get(index..index + content_len)
+            .ok_or(SerializeError::NotEnoughData)?;
+        let content = crate::parse_utf8(content, "Message", index)?.to_string();
+
+        let message = Self {
+            id,
             from,
             to,
-            time: SystemTime::now(),
+            time: time_from_millis(millis),
             content,
-        })
+        };
+        Ok((message, index + content_len))
     }
 }
 
@@ -102,4 +192,59 @@ mod tests {
         assert_eq!(message.to(), &to);
         assert_ne!(message.time(), &SystemTime::now());
     }
+
+    #[test]
+    fn send_and_recive() {
+        let message = Message::new("Zażółć gęślą jaźń\0!".to_string(), 1, 2);
+        let mut buffer = [0xFFu8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut buffer).unwrap();
+
+        assert_eq!(Message::deserialize(&buffer).unwrap().0, message);
+    }
+
+    #[test]
+    fn unique_ids() {
+        let m1 = Message::new("Hi".to_string(), 1, 2);
+        let m2 = Message::new("Hi".to_string(), 1, 2);
+        assert_ne!(m1.id(), m2.id());
+
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        m1.serialize(&mut buffer).unwrap();
+        assert_eq!(Message::deserialize(&buffer).unwrap().0.id(), m1.id());
+    }
+
+    #[test]
+    fn time_precision() {
+        let time = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        let message = Message::with_time("Hi".to_string(), 1, 2, time);
+        assert_eq!(
+            message.time(),
+            &(UNIX_EPOCH + Duration::from_millis(1_600_000_000_123))
+        );
+
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut buffer).unwrap();
+        let received = Message::deserialize(&buffer).unwrap().0;
+        assert_eq!(received.time(), message.time());
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        let message = Message::new("Hi".to_string(), 1, 2);
+        let len = Message::MIN_BYTE_LEN + 2;
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut buffer).unwrap();
+
+        for short in 0..len {
+            assert_eq!(
+                Message::deserialize(&buffer[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+            assert_eq!(
+                message.serialize(&mut vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+        assert_eq!(Message::deserialize(&buffer[..len]).unwrap().0, message);
+    }
 }
@@ -0,0 +1,178 @@
+use crate::CommError;
+use std::{error::Error, fmt};
+use unicode_normalization::is_nfc;
+
+/// Reasons why password was rejected by `PasswordPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PasswordError {
+    /// Password has fewer characters than required minimum.
+    TooShort {
+        /// Minimum number of characters.
+        min: usize,
+        /// Number of characters in password.
+        actual: usize,
+    },
+
+    /// Password takes more bytes than allowed maximum.
+    TooLong {
+        /// Maximum number of bytes.
+        max: usize,
+        /// Number of bytes in password.
+        actual: usize,
+    },
+
+    /// Password contains NUL byte, which many clients treat as end of string.
+    ContainsNul,
+
+    /// Password isn't in Unicode Normalization Form C, so the same password typed on other system
+    /// could be encoded differently.
+    NotNormalized,
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::TooShort { min, actual } => write!(
+                f,
+                "password has {} characters but needs at least {}",
+                actual, min
+            ),
+            PasswordError::TooLong { max, actual } => write!(
+                f,
+                "password has {} bytes but can't have more than {}",
+                actual, max
+            ),
+            PasswordError::ContainsNul => write!(f, "password contains NUL character"),
+            PasswordError::NotNormalized => write!(f, "password is not in Unicode NFC"),
+        }
+    }
+}
+
+impl Error for PasswordError {}
+
+impl From<PasswordError> for CommError {
+    /// Too short password is `CommError::WeakPassword`, every other is
+    /// `CommError::InvalidPassword`. Client can use it to report password rejected by its own
+    /// check the same way as errors received from server.
+    fn from(err: PasswordError) -> Self {
+        match err {
+            PasswordError::TooShort { .. } => CommError::WeakPassword,
+            _ => CommError::InvalidPassword,
+        }
+    }
+}
+
+/// Rules that every password must follow. Server never receives passwords, only login verifiers,
+/// so client enforces policy before it creates verifier, with limits received from server in
+/// `Comm::Limits` turned into policy by `ServerLimits::password_policy`. Default policy uses
+/// `MIN_PASS_CHAR_LEN` and `MAX_PASS_BYTE_LEN`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PasswordPolicy {
+    /// Minimum password length in characters, not bytes.
+    pub min_char_len: usize,
+
+    /// Maximum password length in bytes, not characters.
+    pub max_byte_len: usize,
+}
+
+impl PasswordPolicy {
+    /// Checks if `password` follows policy. Password must be NFC-normalized, can't contain NUL and
+    /// its length must be between `min_char_len` characters and `max_byte_len` bytes.
+    pub fn validate(&self, password: &str) -> Result<(), PasswordError> {
+        let chars = password.chars().count();
+        if chars < self.min_char_len {
+            Err(PasswordError::TooShort {
+                min: self.min_char_len,
+                actual: chars,
+            })
+        } else if password.len() > self.max_byte_len {
+            Err(PasswordError::TooLong {
+                max: self.max_byte_len,
+                actual: password.len(),
+            })
+        } else if password.contains('\0') {
+            Err(PasswordError::ContainsNul)
+        } else if !is_nfc(password) {
+            Err(PasswordError::NotNormalized)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_char_len: crate::MIN_PASS_CHAR_LEN,
+            max_byte_len: crate::MAX_PASS_BYTE_LEN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length() {
+        let policy = PasswordPolicy {
+            min_char_len: 2,
+            max_byte_len: 4,
+        };
+
+        assert_eq!(
+            policy.validate("a"),
+            Err(PasswordError::TooShort { min: 2, actual: 1 })
+        );
+        assert_eq!(policy.validate("ab"), Ok(()));
+        assert_eq!(policy.validate("abcd"), Ok(()));
+        assert_eq!(
+            policy.validate("abcde"),
+            Err(PasswordError::TooLong { max: 4, actual: 5 })
+        );
+        // Three characters but six bytes.
+        assert_eq!(
+            policy.validate("ąęć"),
+            Err(PasswordError::TooLong { max: 4, actual: 6 })
+        );
+    }
+
+    #[test]
+    fn nul() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(policy.validate("ab\0cd"), Err(PasswordError::ContainsNul));
+    }
+
+    #[test]
+    fn normalization() {
+        let policy = PasswordPolicy::default();
+        // "ą" as single code point and as "a" followed by combining ogonek.
+        assert_eq!(policy.validate("\u{105}bcd"), Ok(()));
+        assert_eq!(
+            policy.validate("a\u{328}bcd"),
+            Err(PasswordError::NotNormalized)
+        );
+    }
+
+    #[test]
+    fn display() {
+        let short = PasswordError::TooShort { min: 4, actual: 1 };
+        assert_eq!(
+            short.to_string(),
+            "password has 1 characters but needs at least 4"
+        );
+        let e: Box<dyn Error> = Box::new(PasswordError::ContainsNul);
+        assert_eq!(e.to_string(), "password contains NUL character");
+    }
+
+    #[test]
+    fn comm_error() {
+        let short = PasswordError::TooShort { min: 4, actual: 1 };
+        assert_eq!(CommError::from(short), CommError::WeakPassword);
+        assert_eq!(
+            CommError::from(PasswordError::ContainsNul),
+            CommError::InvalidPassword
+        );
+    }
+}
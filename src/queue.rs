@@ -0,0 +1,345 @@
+use crate::{MessageId, Pending, UserID};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Default maximum number of messages stored for single recipient.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Default time after which leased message that wasn't acknowledged is delivered again.
+pub const DEFAULT_LEASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Number of acknowledged message IDs remembered for every sender. Message resent with one of them
+/// is dropped as duplicate, even though it isn't in queue anymore.
+pub const SEEN_IDS_PER_SENDER: usize = 1024;
+
+#[derive(Debug)]
+struct Entry {
+    message: Pending,
+    leased_until: Option<Instant>,
+}
+
+impl Entry {
+    fn is_available(&self, now: Instant) -> bool {
+        !matches!(self.leased_until, Some(until) if now < until)
+    }
+}
+
+/// Store-and-forward queue of messages waiting for recipients. Messages are stored per recipient
+/// and delivered oldest first. Delivered message is only leased, it stays in queue until it is
+/// acknowledged with `Comm::Ack`. If it isn't acknowledged before lease times out, it is
+/// delivered again. Plain and end-to-end encrypted messages share the same queue and are delivered
+/// as `Comm::Message` or `Comm::SealedMessage`, see `Pending::to_comm`. Use `Outbox` to deliver
+/// them in order with `Comm::Deliver` and `Comm::DeliverSealed` instead.
+#[derive(Debug)]
+pub struct MessageQueue {
+    capacity: usize,
+    lease_timeout: Duration,
+    queues: HashMap<UserID, VecDeque<Entry>>,
+    // Recently acknowledged message IDs of every sender, oldest first.
+    seen: HashMap<UserID, VecDeque<MessageId>>,
+}
+
+impl MessageQueue {
+    /// Creates empty queue with `DEFAULT_QUEUE_CAPACITY` and `DEFAULT_LEASE_TIMEOUT`.
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_QUEUE_CAPACITY, DEFAULT_LEASE_TIMEOUT)
+    }
+
+    /// Creates empty queue that stores at most `capacity` messages per recipient and delivers
+    /// message again `lease_timeout` after it was leased.
+    pub fn with_limits(capacity: usize, lease_timeout: Duration) -> Self {
+        Self {
+            capacity,
+            lease_timeout,
+            queues: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Returns maximum number of messages stored per recipient.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns time after which not acknowledged message is delivered again.
+    pub fn lease_timeout(&self) -> Duration {
+        self.lease_timeout
+    }
+
+    /// Stores message for its recipient. Message with sender and ID that is already in queue or
+    /// was recently acknowledged is ignored, so resending it is safe, e.g. when `Comm::Ack` was
+    /// lost. Returns message back if recipient queue is full.
+    pub fn enqueue<M: Into<Pending>>(&mut self, message: M) -> Result<(), Pending> {
+        let message = message.into();
+        let seen = self
+            .seen
+            .get(message.from())
+            .is_some_and(|seen| seen.contains(&message.id()));
+        if seen {
+            return Ok(());
+        }
+
+        let queue = self.queues.entry(*message.to()).or_default();
+        let duplicate = queue.iter().any(|entry| {
+            entry.message.id() == message.id() && entry.message.from() == message.from()
+        });
+        if duplicate {
+            return Ok(());
+        }
+        if queue.len() >= self.capacity {
+            return Err(message);
+        }
+
+        queue.push_back(Entry {
+            message,
+            leased_until: None,
+        });
+        Ok(())
+    }
+
+    /// Leases oldest message for `recipient` that isn't leased at `now`, or whose lease timed
+    /// out. Returns `None` if there is no such message.
+    pub fn lease_at(&mut self, recipient: UserID, now: Instant) -> Option<Pending> {
+        let lease_timeout = self.lease_timeout;
+        let entry = self
+            .queues
+            .get_mut(&recipient)?
+            .iter_mut()
+            .find(|entry| entry.is_available(now))?;
+        entry.leased_until = Some(now + lease_timeout);
+        Some(entry.message.clone())
+    }
+
+    /// Leases oldest available message for `recipient`. See `lease_at`.
+    pub fn lease(&mut self, recipient: UserID) -> Option<Pending> {
+        self.lease_at(recipient, Instant::now())
+    }
+
+    /// Removes leased message `id` sent by `from` from queue of `recipient`. Returns false if
+    /// there is no such leased message.
+    pub fn ack(&mut self, recipient: UserID, from: UserID, id: MessageId) -> bool {
+        let queue = match self.queues.get_mut(&recipient) {
+            Some(queue) => queue,
+            None => return false,
+        };
+        let index = queue.iter().position(|entry| {
+            entry.message.from() == &from
+                && entry.message.id() == id
+                && entry.leased_until.is_some()
+        });
+
+        match index {
+            Some(index) => {
+                queue.remove(index);
+                if queue.is_empty() {
+                    self.queues.remove(&recipient);
+                }
+
+                let seen = self.seen.entry(from).or_default();
+                if seen.len() >= SEEN_IDS_PER_SENDER {
+                    seen.pop_front();
+                }
+                seen.push_back(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Releases all leases of `recipient`, e.g. after it disconnected, so its messages can be
+    /// delivered again without waiting for timeout.
+    pub fn release(&mut self, recipient: UserID) {
+        if let Some(queue) = self.queues.get_mut(&recipient) {
+            for entry in queue {
+                entry.leased_until = None;
+            }
+        }
+    }
+
+    /// Returns number of messages stored for `recipient`, including leased ones.
+    pub fn len(&self, recipient: UserID) -> usize {
+        self.queues.get(&recipient).map_or(0, VecDeque::len)
+    }
+
+    /// Returns true if there are no messages for any recipient.
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
+impl Default for MessageQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Comm, Message, SealedMessage, SEALED_NONCE_LEN};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn message(content: &str, from: UserID, to: UserID) -> Message {
+        Message::new(content.to_string(), from, to)
+    }
+
+    /// Returns content of leased plain message.
+    fn content(leased: Option<Pending>) -> String {
+        match leased {
+            Some(Pending::Plain(message)) => message.content().to_string(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn oldest_first() {
+        let mut queue = MessageQueue::new();
+        queue.enqueue(message("1", 1, 2)).unwrap();
+        queue.enqueue(message("other", 1, 3)).unwrap();
+        queue.enqueue(message("2", 4, 2)).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(content(queue.lease_at(2, now)), "1");
+        assert_eq!(content(queue.lease_at(2, now)), "2");
+        assert_eq!(queue.lease_at(2, now), None);
+        assert_eq!(content(queue.lease_at(3, now)), "other");
+        assert_eq!(queue.lease_at(5, now), None);
+    }
+
+    #[test]
+    fn ack() {
+        let mut queue = MessageQueue::new();
+        let m = message("Hi", 1, 2);
+        let id = m.id();
+        queue.enqueue(m).unwrap();
+
+        // Message can't be acknowledged before it is delivered.
+        assert!(!queue.ack(2, 1, id));
+        queue.lease(2).unwrap();
+        assert!(!queue.ack(3, 1, id));
+        // Only message of given sender is acknowledged.
+        assert!(!queue.ack(2, 4, id));
+        assert!(queue.ack(2, 1, id));
+        assert!(!queue.ack(2, 1, id));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn sealed() {
+        let mut queue = MessageQueue::with_limits(10, TIMEOUT);
+        let sealed = SealedMessage {
+            from: 1,
+            to: 2,
+            id: 3,
+            time: std::time::UNIX_EPOCH,
+            nonce: [0u8; SEALED_NONCE_LEN],
+            ciphertext: vec![4, 5, 6],
+        };
+        queue.enqueue(message("Hi", 1, 2)).unwrap();
+        queue.enqueue(sealed.clone()).unwrap();
+        // The same sealed message send again is dropped.
+        queue.enqueue(sealed.clone()).unwrap();
+        assert_eq!(queue.len(2), 2);
+        let now = Instant::now();
+
+        let plain = queue.lease_at(2, now).unwrap();
+        let leased = queue.lease_at(2, now).unwrap();
+        assert_eq!(leased, Pending::Sealed(sealed.clone()));
+        assert_eq!(leased.to_comm(), Comm::SealedMessage(sealed.clone()));
+        assert!(queue.ack(2, 1, plain.id()));
+
+        // Not acknowledged sealed message is delivered again.
+        assert_eq!(queue.lease_at(2, now + TIMEOUT), Some(leased));
+        assert!(queue.ack(2, 1, 3));
+        assert!(queue.is_empty());
+        // It is dropped when sender sends it again after acknowledgement.
+        queue.enqueue(sealed).unwrap();
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn lease_timeout() {
+        let mut queue = MessageQueue::with_limits(10, TIMEOUT);
+        queue.enqueue(message("Hi", 1, 2)).unwrap();
+        let now = Instant::now();
+
+        let first = queue.lease_at(2, now).unwrap();
+        assert_eq!(
+            queue.lease_at(2, now + TIMEOUT - Duration::from_millis(1)),
+            None
+        );
+        let again = queue.lease_at(2, now + TIMEOUT).unwrap();
+        assert_eq!(again, first);
+        assert_eq!(queue.len(2), 1);
+    }
+
+    #[test]
+    fn release() {
+        let mut queue = MessageQueue::with_limits(10, TIMEOUT);
+        queue.enqueue(message("Hi", 1, 2)).unwrap();
+        let now = Instant::now();
+
+        let first = queue.lease_at(2, now).unwrap();
+        queue.release(2);
+        assert_eq!(queue.lease_at(2, now), Some(first));
+    }
+
+    #[test]
+    fn capacity() {
+        let mut queue = MessageQueue::with_limits(2, TIMEOUT);
+        queue.enqueue(message("1", 1, 2)).unwrap();
+        queue.enqueue(message("2", 1, 2)).unwrap();
+        let third = message("3", 1, 2);
+        assert_eq!(queue.enqueue(third.clone()), Err(Pending::Plain(third)));
+        // Other recipients have their own limit.
+        queue.enqueue(message("1", 1, 3)).unwrap();
+
+        let id = queue.lease(2).unwrap().id();
+        queue.ack(2, 1, id);
+        queue.enqueue(message("3", 1, 2)).unwrap();
+        assert_eq!(queue.len(2), 2);
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut queue = MessageQueue::with_limits(1, TIMEOUT);
+        let m = message("Hi", 1, 2);
+        queue.enqueue(m.clone()).unwrap();
+        // The same message send again doesn't take space, even if queue is full.
+        assert_eq!(queue.enqueue(m.clone()), Ok(()));
+        assert_eq!(queue.len(2), 1);
+
+        // Other sender can use the same ID.
+        let other = Message::with_id(m.id(), "Hi".to_string(), 3, 2, *m.time());
+        let mut queue = MessageQueue::new();
+        queue.enqueue(m).unwrap();
+        queue.enqueue(other).unwrap();
+        assert_eq!(queue.len(2), 2);
+    }
+
+    #[test]
+    fn duplicate_after_ack() {
+        let mut queue = MessageQueue::new();
+        let m = message("Hi", 1, 2);
+        queue.enqueue(m.clone()).unwrap();
+        queue.lease(2).unwrap();
+        assert!(queue.ack(2, 1, m.id()));
+
+        // Sender didn't get `Comm::Ack` and sends message again.
+        queue.enqueue(m.clone()).unwrap();
+        assert!(queue.is_empty());
+
+        // Only limited number of IDs is remembered.
+        for i in 0..SEEN_IDS_PER_SENDER {
+            let other = Message::with_id(i as MessageId, i.to_string(), 1, 2, *m.time());
+            queue.enqueue(other).unwrap();
+            let id = queue.lease(2).unwrap().id();
+            assert!(queue.ack(2, 1, id));
+        }
+        queue.enqueue(m).unwrap();
+        assert_eq!(queue.len(2), 1);
+    }
+}
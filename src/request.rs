@@ -0,0 +1,185 @@
+use crate::Comm;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Identifier of client request. Server copies it to `Comm::Response`, so client knows which
+/// request was answered.
+pub type RequestId = u32;
+
+/// Default time after which request without response is expired.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client side list of requests waiting for response. It wraps requests in `Comm::Request` with
+/// unique ID, matches `Comm::Response` with them using `complete` and expires the ones that never
+/// got answer. Every request carries `context` chosen by caller, e.g. what should be done when it
+/// is answered.
+#[derive(Debug)]
+pub struct PendingRequests<T = ()> {
+    timeout: Duration,
+    next_id: RequestId,
+    pending: HashMap<RequestId, (T, Instant)>,
+}
+
+impl<T> PendingRequests<T> {
+    /// Creates empty list with `DEFAULT_REQUEST_TIMEOUT`.
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Creates empty list that expires requests `timeout` after they were sent.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_id: 1,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Returns time after which request is expired.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Records request sent at `now` and returns it wrapped in `Comm::Request`, ready to be sent.
+    pub fn request_at(&mut self, comm: Comm, context: T, now: Instant) -> Comm {
+        let mut id = self.next_id;
+        // Skip IDs of requests that are still waiting after counter wrapped around.
+        while self.pending.contains_key(&id) {
+            id = id.wrapping_add(1);
+        }
+        self.next_id = id.wrapping_add(1);
+
+        self.pending.insert(id, (context, now + self.timeout));
+        Comm::Request {
+            id,
+            request: Box::new(comm),
+        }
+    }
+
+    /// Records request sent now. See `request_at`.
+    pub fn request(&mut self, comm: Comm, context: T) -> Comm {
+        self.request_at(comm, context, Instant::now())
+    }
+
+    /// Removes request answered by `Comm::Response` with `id` and returns its context. Returns
+    /// `None` if there is no such request, e.g. because it expired.
+    pub fn complete(&mut self, id: RequestId) -> Option<T> {
+        self.pending.remove(&id).map(|(context, _)| context)
+    }
+
+    /// Removes requests that didn't get response until `now` and returns their IDs and contexts.
+    pub fn expire_at(&mut self, now: Instant) -> Vec<(RequestId, T)> {
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| now >= *deadline)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut expired: Vec<_> = expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id).map(|(context, _)| (id, context)))
+            .collect();
+        expired.sort_by_key(|(id, _)| *id);
+        expired
+    }
+
+    /// Removes requests that didn't get response until now. See `expire_at`.
+    pub fn expire(&mut self) -> Vec<(RequestId, T)> {
+        self.expire_at(Instant::now())
+    }
+
+    /// Returns number of requests waiting for response.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns true if no request is waiting for response.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthParams, PasswordChange, PROOF_LEN};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Returns ID of wrapped request.
+    fn id(comm: &Comm) -> RequestId {
+        match comm {
+            Comm::Request { id, .. } => *id,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn pipelined() {
+        let mut pending = PendingRequests::new();
+        let friend = pending.request(Comm::AddFriend(2), "friend");
+        let password = pending.request(
+            Comm::ChangePassword(PasswordChange {
+                params: AuthParams::new(),
+                encrypted_key: [0; PROOF_LEN],
+                proof: [0; PROOF_LEN],
+            }),
+            "password",
+        );
+        assert_ne!(id(&friend), id(&password));
+        assert_eq!(pending.len(), 2);
+
+        // Responses can come in any order.
+        assert_eq!(pending.complete(id(&password)), Some("password"));
+        assert_eq!(pending.complete(id(&friend)), Some("friend"));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn unknown_response() {
+        let mut pending = PendingRequests::new();
+        let request = pending.request(Comm::AddFriend(2), ());
+
+        assert_eq!(pending.complete(id(&request) + 1), None);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending.complete(id(&request)), Some(()));
+        assert_eq!(pending.complete(id(&request)), None);
+    }
+
+    #[test]
+    fn expire() {
+        let mut pending = PendingRequests::with_timeout(TIMEOUT);
+        let now = Instant::now();
+        let first = pending.request_at(Comm::AddFriend(2), 1, now);
+        let second = pending.request_at(Comm::AddFriend(3), 2, now + Duration::from_secs(1));
+
+        assert!(pending
+            .expire_at(now + TIMEOUT - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(pending.expire_at(now + TIMEOUT), [(id(&first), 1)]);
+        assert_eq!(pending.len(), 1);
+
+        // Late response to expired request isn't matched.
+        assert_eq!(pending.complete(id(&first)), None);
+        assert_eq!(pending.complete(id(&second)), Some(2));
+    }
+
+    #[test]
+    fn id_wraps_around() {
+        let mut pending = PendingRequests::new();
+        pending.next_id = RequestId::MAX;
+        let last = pending.request(Comm::AddFriend(2), ());
+        let first = pending.request(Comm::AddFriend(2), ());
+        assert_eq!(id(&last), RequestId::MAX);
+        assert_eq!(id(&first), 0);
+    }
+}
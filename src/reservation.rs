@@ -0,0 +1,93 @@
+use crate::{CommError, UserID};
+use std::time::{Duration, SystemTime};
+
+/// Default time for which ID sent in `Comm::Connected` stays reserved for registration.
+pub const RESERVATION_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Server side record of ID reserved for single connected client. Client can create account with
+/// this ID using `Comm::Register` until reservation expires.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdReservation {
+    id: UserID,
+    expires_at: SystemTime,
+}
+
+impl IdReservation {
+    /// Reserves `id` for `ttl`.
+    pub fn new(id: UserID, ttl: Duration) -> Self {
+        Self::with_expiry(id, SystemTime::now() + ttl)
+    }
+
+    /// Reserves `id` until `expires_at`.
+    pub fn with_expiry(id: UserID, expires_at: SystemTime) -> Self {
+        Self { id, expires_at }
+    }
+
+    /// Returns reserved ID.
+    pub fn id(&self) -> UserID {
+        self.id
+    }
+
+    /// Returns time at which reservation expires.
+    pub fn expires_at(&self) -> &SystemTime {
+        &self.expires_at
+    }
+
+    /// Returns true if reservation is expired at `now`.
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Checks if client can register account with `id` at `now`. Returns `CommError::IdTaken` if
+    /// `id` isn't the reserved one and `CommError::IdExpired` if reservation expired. Server must
+    /// still check that no account with `id` exists.
+    pub fn validate_at(&self, id: UserID, now: SystemTime) -> Result<(), CommError> {
+        if id != self.id {
+            Err(CommError::IdTaken)
+        } else if self.is_expired_at(now) {
+            Err(CommError::IdExpired)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks if client can register account with `id` now. See `validate_at`.
+    pub fn validate(&self, id: UserID) -> Result<(), CommError> {
+        self.validate_at(id, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn valid() {
+        let reservation = IdReservation::new(7, RESERVATION_TTL);
+        assert_eq!(reservation.id(), 7);
+        assert_eq!(reservation.validate(7), Ok(()));
+    }
+
+    #[test]
+    fn other_id() {
+        let reservation = IdReservation::new(7, RESERVATION_TTL);
+        assert_eq!(reservation.validate(8), Err(CommError::IdTaken));
+    }
+
+    #[test]
+    fn expired() {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let reservation = IdReservation::with_expiry(7, expires_at);
+
+        assert_eq!(
+            reservation.validate_at(7, expires_at - Duration::from_millis(1)),
+            Ok(())
+        );
+        assert_eq!(
+            reservation.validate_at(7, expires_at),
+            Err(CommError::IdExpired)
+        );
+        assert_eq!(reservation.validate(7), Err(CommError::IdExpired));
+    }
+}
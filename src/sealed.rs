@@ -0,0 +1,200 @@
+use std::{convert::TryInto, time::SystemTime};
+
+use crate::{
+    message::{time_from_millis, time_to_millis},
+    serialize::{Serialize, SerializeError},
+    MessageId, UserID, USER_ID_SIZE,
+};
+
+/// Size of X25519 public key in bytes.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Size of ChaCha20-Poly1305 nonce in bytes.
+pub const SEALED_NONCE_LEN: usize = 12;
+
+/// Size of ChaCha20-Poly1305 authentication tag in bytes. Every ciphertext is longer than content
+/// by this number of bytes.
+pub const SEALED_TAG_LEN: usize = 16;
+
+/// X25519 public key of user. Users publish it with `Comm::PublishKey`, so other users can send
+/// them `SealedMessage`.
+pub type PublicKey = [u8; PUBLIC_KEY_LEN];
+
+/// Message with end-to-end encrypted content. Only recipient `to` can read content, server sees
+/// only sender, recipient, message ID and time, which it needs for routing. Creating and opening it requires
+/// `e2e` feature, but it can be routed and stored without it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SealedMessage {
+    pub(crate) from: UserID,
+    pub(crate) to: UserID,
+    pub(crate) id: MessageId,
+    pub(crate) time: SystemTime,
+    pub(crate) nonce: [u8; SEALED_NONCE_LEN],
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+impl SealedMessage {
+    // Header, nonce = 12 and ciphertext length = 2.
+    pub(crate) const MIN_BYTE_LEN: usize = SealedMessage::HEADER_LEN + SEALED_NONCE_LEN + 2;
+
+    // from = 8, to = 8, id = 8 and time = 8.
+    const HEADER_LEN: usize = 2 * USER_ID_SIZE + 8 + 8;
+
+    /// Returns sender ID.
+    pub fn from(&self) -> &UserID {
+        &self.from
+    }
+
+    /// Returns reciever ID.
+    pub fn to(&self) -> &UserID {
+        &self.to
+    }
+
+    /// Returns message ID.
+    pub fn id(&self) -> MessageId {
+        self.id
+    }
+
+    /// Time when message was sent.
+    pub fn time(&self) -> &SystemTime {
+        &self.time
+    }
+
+    /// Encrypted content followed by authentication tag.
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    /// Returns sender, recipient, message ID and time as written on the wire. They are
+    /// authenticated together with content, so server can't redirect or backdate message.
+    pub(crate) fn header(&self) -> [u8; SealedMessage::HEADER_LEN] {
+        let mut header = [0u8; SealedMessage::HEADER_LEN];
+        header[..8].copy_from_slice(&self.from.to_le_bytes());
+        header[8..16].copy_from_slice(&self.to.to_le_bytes());
+        header[16..24].copy_from_slice(&self.id.to_le_bytes());
+        header[24..].copy_from_slice(&time_to_millis(&self.time).to_le_bytes());
+        header
+    }
+}
+
+impl Serialize for SealedMessage {
+    fn encoded_len(&self) -> usize {
+        SealedMessage::MIN_BYTE_LEN + self.ciphertext.len()
+    }
+
+    /// Writes sender and reciever IDs, message ID, send time as milliseconds since Unix epoch, nonce and
+    /// ciphertext preceded by its length in bytes.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        // Ciphertext longer than u16 can't be described by length field.
+        if self.ciphertext.len() > u16::MAX as usize {
+            return Err(SerializeError::CountTooLarge {
+                type_name: "SealedMessage".into(),
+                offset: SealedMessage::MIN_BYTE_LEN - 2,
+                count: self.ciphertext.len(),
+                max: u16::MAX as usize,
+            });
+        }
+        if buffer.len() < SealedMessage::MIN_BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let mut index = crate::write_bytes_to_buffer(buffer, &self.header())?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.nonce)?;
+        index += crate::write_bytes_to_buffer(
+            &mut buffer[index..],
+            &(self.ciphertext.len() as u16).to_le_bytes(),
+        )?;
+        index += crate::write_bytes_to_buffer(&mut buffer[index..], &self.ciphertext)?;
+
+        Ok(index)
+    }
+
+    /// Reads message written by `serialize`.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < SealedMessage::MIN_BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
+
+        let from = crate::parse_id_from_bytes(buffer)?;
+        let mut index = USER_ID_SIZE;
+        let to = crate::parse_id_from_bytes(&buffer[index..])?;
+        index += USER_ID_SIZE;
+        let id = u64::from_le_bytes(buffer[index..index + 8].try_into().unwrap());
+        index += 8;
+        let millis = u64::from_le_bytes(buffer[index..index + 8].try_into().unwrap());
+        index += 8;
+        let nonce = buffer[index..index + SEALED_NONCE_LEN].try_into().unwrap();
+        index += SEALED_NONCE_LEN;
+        let len = u16::from_le_bytes(buffer[index..index + 2].try_into().unwrap()) as usize;
+        index += 2;
+        let ciphertext = buffer
+            .get(index..index + len)
+            .ok_or(SerializeError::NotEnoughData)?
+            .to_vec();
+
+        let message = Self {
+            from,
+            to,
+            id,
+            time: time_from_millis(millis),
+            nonce,
+            ciphertext,
+        };
+        Ok((message, index + len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed() -> SealedMessage {
+        SealedMessage {
+            from: 1,
+            to: 2,
+            id: 9,
+            time: time_from_millis(0x0304),
+            nonce: [5u8; SEALED_NONCE_LEN],
+            ciphertext: vec![6, 7, 8],
+        }
+    }
+
+    #[test]
+    fn send_and_recive() {
+        let message = sealed();
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut buffer).unwrap();
+
+        let mut bytes = vec![1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(&[9, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[4, 3, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[5u8; SEALED_NONCE_LEN]);
+        bytes.extend_from_slice(&[3, 0, 6, 7, 8]);
+        assert_eq!(&buffer[..bytes.len()], &bytes[..]);
+        assert_eq!(SealedMessage::deserialize(&buffer).unwrap().0, message);
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        let message = sealed();
+        let len = SealedMessage::MIN_BYTE_LEN + 3;
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        message.serialize(&mut buffer).unwrap();
+
+        for short in 0..len {
+            assert_eq!(
+                SealedMessage::deserialize(&buffer[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+            assert_eq!(
+                message.serialize(&mut vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+        assert_eq!(
+            SealedMessage::deserialize(&buffer[..len]).unwrap().0,
+            message
+        );
+    }
+}
@@ -1,22 +1,253 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt,
+    io::{self, Write},
+};
+
 /// This trait should be implemented by every object that requires to be serialized.
-pub trait Serialize {
-    type Item;
+pub trait Serialize: Sized {
+    /// Returns number of bytes that `serialize` writes.
+    fn encoded_len(&self) -> usize;
+
+    /// Serializing object to the beginning of u8 buffer. Returns number of bytes written, which is
+    /// always `encoded_len`, on success or `SerializeError`.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError>;
+
+    /// Deserializing object from the beginning of u8 buffer. Returns `Self` and number of bytes
+    /// consumed on success or `SerializeError`. Bytes after object are ignored.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError>;
+
+    /// Serializes object to new vector of exactly `encoded_len` bytes.
+    fn serialize_to_vec(&self) -> Result<Vec<u8>, SerializeError> {
+        let mut buffer = vec![0u8; self.encoded_len()];
+        let len = self.serialize(&mut buffer)?;
+        buffer.truncate(len);
+        Ok(buffer)
+    }
 
-    /// Serializing object to u8 buffer. Returns empty tuple (nothing) on success or
-    /// `SerializeError`.
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), SerializeError>;
+    /// Serializes object and writes it to `writer`. Returns number of bytes written.
+    /// `SerializeError` is returned as error of `io::ErrorKind::InvalidInput` kind.
+    fn serialize_into<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        let buffer = self
+            .serialize_to_vec()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        writer.write_all(&buffer)?;
+        Ok(buffer.len())
+    }
 
-    /// Deserializing object from u8 buffer. Returns `Self` on success or `SerializeError`.
-    fn deserialize(buffer: &[u8]) -> Result<Self::Item, SerializeError>;
+    /// Deserializes object that takes whole `buffer`, e.g. single frame. Returns
+    /// `SerializeError::TrailingData` if there are any bytes after it.
+    fn deserialize_exact(buffer: &[u8]) -> Result<Self, SerializeError> {
+        let (item, len) = Self::deserialize(buffer)?;
+        check_trailing(buffer, len, short_type_name::<Self>())?;
+        Ok(item)
+    }
+}
+
+/// Returns `SerializeError::TrailingData` if `buffer` is longer than `len` bytes of `type_name`.
+pub(crate) fn check_trailing(
+    buffer: &[u8],
+    len: usize,
+    type_name: &'static str,
+) -> Result<(), SerializeError> {
+    if buffer.len() > len {
+        Err(SerializeError::TrailingData {
+            type_name: type_name.into(),
+            offset: len,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns name of type without module path, e.g. `Comm`.
+fn short_type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 /// This type describes errors that can occur when data is serialized/deserialized. They are not
-/// transmitted between client and server.
-#[derive(Debug, PartialEq)]
+/// transmitted between client and server. Errors about malformed data carry name of type that was
+/// serialized/deserialized and offset of bad bytes counted from the beginning of that type.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SerializeError {
     /// It occurs at reading when some unexpected data has been read.
     UnknownSignature(u8),
 
     /// Buffer is too small to fit data that we need to read or write.
     NotEnoughData,
+
+    /// String field doesn't contain valid UTF-8.
+    InvalidUtf8 {
+        /// Type that contains string.
+        type_name: Cow<'static, str>,
+        /// Offset of first invalid byte.
+        offset: usize,
+    },
+
+    /// String is longer than its field or length prefix allows.
+    StringTooLong {
+        /// Type that contains string.
+        type_name: Cow<'static, str>,
+        /// Offset of string field.
+        offset: usize,
+        /// String length in bytes.
+        len: usize,
+        /// Maximum length in bytes.
+        max: usize,
+    },
+
+    /// Number of items is bigger than its count field allows.
+    CountTooLarge {
+        /// Type that contains items.
+        type_name: Cow<'static, str>,
+        /// Offset of count field.
+        offset: usize,
+        /// Number of items.
+        count: usize,
+        /// Maximum number of items.
+        max: usize,
+    },
+
+    /// Buffer contains unexpected bytes after the end of value.
+    TrailingData {
+        /// Type that was read.
+        type_name: Cow<'static, str>,
+        /// Offset of first unexpected byte.
+        offset: usize,
+    },
+
+    /// Field contains value that is out of allowed range.
+    InvalidValue {
+        /// Type that contains field.
+        type_name: Cow<'static, str>,
+        /// Offset of field.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::UnknownSignature(sig) => write!(f, "unknown signature {:#04x}", sig),
+            SerializeError::NotEnoughData => write!(f, "not enough data"),
+            SerializeError::InvalidUtf8 { type_name, offset } => {
+                write!(f, "invalid UTF-8 in {} at offset {}", type_name, offset)
+            }
+            SerializeError::StringTooLong {
+                type_name,
+                offset,
+                len,
+                max,
+            } => write!(
+                f,
+                "string of {} bytes at offset {} of {} is longer than {} bytes",
+                len, offset, type_name, max
+            ),
+            SerializeError::CountTooLarge {
+                type_name,
+                offset,
+                count,
+                max,
+            } => write!(
+                f,
+                "count {} at offset {} of {} is bigger than {}",
+                count, offset, type_name, max
+            ),
+            SerializeError::TrailingData { type_name, offset } => {
+                write!(f, "trailing data after {} at offset {}", type_name, offset)
+            }
+            SerializeError::InvalidValue { type_name, offset } => {
+                write!(f, "invalid value at offset {} of {}", offset, type_name)
+            }
+        }
+    }
+}
+
+impl Error for SerializeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vec_and_writer() {
+        let hello = crate::Hello::with_versions(1, 2, 3);
+        let bytes = hello.serialize_to_vec().unwrap();
+        assert_eq!(bytes.len(), hello.encoded_len());
+
+        let mut writer = Vec::new();
+        assert_eq!(hello.serialize_into(&mut writer).unwrap(), bytes.len());
+        hello.serialize_into(&mut writer).unwrap();
+        assert_eq!(writer[..bytes.len()], bytes[..]);
+
+        // Values can be read one after another.
+        let (first, len) = crate::Hello::deserialize(&writer).unwrap();
+        let (second, _) = crate::Hello::deserialize(&writer[len..]).unwrap();
+        assert_eq!((first, second), (hello, hello));
+        assert_eq!(
+            crate::Hello::deserialize_exact(&writer),
+            Err(SerializeError::TrailingData {
+                type_name: "Hello".into(),
+                offset: len
+            })
+        );
+    }
+
+    #[test]
+    fn writer_error() {
+        let message = crate::Message::new("a".repeat(u16::MAX as usize + 1), 1, 2);
+        let err = message.serialize_into(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn type_name() {
+        assert_eq!(short_type_name::<crate::Message>(), "Message");
+    }
+
+    #[test]
+    fn display() {
+        let e = SerializeError::StringTooLong {
+            type_name: "Message".into(),
+            offset: 34,
+            len: 70000,
+            max: 65535,
+        };
+        assert_eq!(
+            e.to_string(),
+            "string of 70000 bytes at offset 34 of Message is longer than 65535 bytes"
+        );
+        assert_eq!(
+            SerializeError::UnknownSignature(0xFF).to_string(),
+            "unknown signature 0xff"
+        );
+        let e: Box<dyn Error> = Box::new(SerializeError::TrailingData {
+            type_name: "Comm".into(),
+            offset: 1,
+        });
+        assert_eq!(e.to_string(), "trailing data after Comm at offset 1");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        for e in [
+            SerializeError::UnknownSignature(7),
+            SerializeError::NotEnoughData,
+            SerializeError::InvalidUtf8 {
+                type_name: "Message".into(),
+                offset: 3,
+            },
+            SerializeError::TrailingData {
+                type_name: "Comm".into(),
+                offset: 1,
+            },
+        ] {
+            let json = serde_json::to_string(&e).unwrap();
+            assert_eq!(serde_json::from_str::<SerializeError>(&json).unwrap(), e);
+        }
+    }
 }
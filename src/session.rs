@@ -0,0 +1,178 @@
+use crate::{
+    message::{time_from_millis, time_to_millis},
+    UserID,
+};
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+use subtle::ConstantTimeEq;
+
+/// Size of session token in bytes.
+pub const SESSION_TOKEN_LEN: usize = 32;
+
+/// Default time after which session expires and client must log in again.
+pub const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Random, opaque token that lets client authenticate again with `Comm::Resume` after reconnect
+/// without knowing password. Client must keep it secret, just like password. Tokens are compared
+/// in constant time and serde never writes them.
+#[derive(Clone)]
+pub struct SessionToken([u8; SESSION_TOKEN_LEN]);
+
+impl SessionToken {
+    /// Creates new random token.
+    ///
+    /// # Panics
+    /// This function will panic if operating system can't provide random bytes.
+    pub fn new() -> Self {
+        let mut token = [0u8; SESSION_TOKEN_LEN];
+        getrandom::getrandom(&mut token).expect("Can't generate random session token.");
+        Self(token)
+    }
+
+    /// Creates token from bytes returned by `as_bytes`.
+    pub fn from_bytes(bytes: [u8; SESSION_TOKEN_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns token as bytes.
+    pub fn as_bytes(&self) -> &[u8; SESSION_TOKEN_LEN] {
+        &self.0
+    }
+}
+
+impl Default for SessionToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for SessionToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+// Token is not printed, so it won't end in logs.
+impl fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionToken(..)")
+    }
+}
+
+/// Server side record of session. Server sends its token and expiry time in `Comm::Session` right
+/// after `Comm::User` and accepts `Comm::Resume` with the same token until session expires.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Session {
+    id: UserID,
+    // Token is never written by serde, loaded session gets new one.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    token: SessionToken,
+    expires_at: SystemTime,
+}
+
+impl Session {
+    /// Creates session for user `id` with new random token that expires after `ttl`.
+    pub fn new(id: UserID, ttl: Duration) -> Self {
+        Self::with_expiry(id, SystemTime::now() + ttl)
+    }
+
+    /// Creates session for user `id` with new random token that expires at `expires_at`. Time is
+    /// truncated to milliseconds because this is precision used on the wire.
+    pub fn with_expiry(id: UserID, expires_at: SystemTime) -> Self {
+        Self {
+            id,
+            token: SessionToken::new(),
+            expires_at: time_from_millis(time_to_millis(&expires_at)),
+        }
+    }
+
+    /// Returns ID of user that owns session.
+    pub fn id(&self) -> UserID {
+        self.id
+    }
+
+    /// Returns session token.
+    pub fn token(&self) -> &SessionToken {
+        &self.token
+    }
+
+    /// Returns time at which session expires.
+    pub fn expires_at(&self) -> &SystemTime {
+        &self.expires_at
+    }
+
+    /// Returns true if session is expired at `now`.
+    pub fn is_expired_at(&self, now: SystemTime) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Returns true if session is already expired.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(SystemTime::now())
+    }
+
+    /// Returns true if `token` belongs to this session and session isn't expired at `now`. Tokens
+    /// are compared in constant time.
+    pub fn validate_at(&self, token: &SessionToken, now: SystemTime) -> bool {
+        self.token == *token && !self.is_expired_at(now)
+    }
+
+    /// Returns true if `token` belongs to this session and session isn't expired yet.
+    pub fn validate(&self, token: &SessionToken) -> bool {
+        self.validate_at(token, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn valid_token() {
+        let session = Session::new(1, SESSION_TTL);
+        assert_eq!(session.id(), 1);
+        assert!(!session.is_expired());
+        assert!(session.validate(&session.token().clone()));
+    }
+
+    #[test]
+    fn other_token() {
+        let session = Session::new(1, SESSION_TTL);
+        assert!(!session.validate(&SessionToken::new()));
+    }
+
+    #[test]
+    fn expired() {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let session = Session::with_expiry(1, expires_at);
+        let token = session.token().clone();
+
+        assert!(session.validate_at(&token, expires_at - Duration::from_millis(1)));
+        assert!(!session.validate_at(&token, expires_at));
+        assert!(session.is_expired());
+    }
+
+    #[test]
+    fn unique_tokens() {
+        assert_ne!(SessionToken::new(), SessionToken::new());
+    }
+
+    #[test]
+    fn equal_tokens() {
+        let token = SessionToken::from_bytes([0xAB; SESSION_TOKEN_LEN]);
+        assert_eq!(token, SessionToken::from_bytes([0xAB; SESSION_TOKEN_LEN]));
+        let mut other = [0xAB; SESSION_TOKEN_LEN];
+        other[SESSION_TOKEN_LEN - 1] = 0;
+        assert_ne!(token, SessionToken::from_bytes(other));
+    }
+
+    #[test]
+    fn debug_hides_token() {
+        let token = SessionToken::from_bytes([0xAB; SESSION_TOKEN_LEN]);
+        assert_eq!(format!("{:?}", token), "SessionToken(..)");
+    }
+}
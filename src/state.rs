@@ -0,0 +1,589 @@
+use crate::{Comm, CommError};
+
+/// Side of connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Side that connects to server.
+    Client,
+    /// Side that accepts connections.
+    Server,
+}
+
+impl Role {
+    /// Returns the other side of connection.
+    pub fn peer(self) -> Self {
+        match self {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        }
+    }
+}
+
+/// Stage of connection lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolPhase {
+    /// Client must send `Comm::Hello`, server answers with `Hello`, `Limits` and `Connected`.
+    AwaitingHello,
+    /// Handshake is done, client can `Login`, `Resume` or `Register`.
+    Connected,
+    /// User is logged and can use everything except authentication.
+    Authenticated,
+    /// Connection is being closed, nothing more can be sent.
+    Closing,
+}
+
+/// State machine of connection lifecycle, `AwaitingHello` → `Connected` → `Authenticated` →
+/// `Closing`. It checks every `Comm` sent or received against rules described in `Comm` docs.
+/// Both methods return next state, or `CommError::InvalidOperation` if `Comm` isn't allowed now,
+/// in which case state doesn't change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolState {
+    role: Role,
+    phase: ProtocolPhase,
+    // Client sent `Login` or `Resume` that wasn't answered yet, so next `User` means success.
+    login_pending: bool,
+}
+
+impl ProtocolState {
+    /// Creates state of newly opened connection for given side.
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            phase: ProtocolPhase::AwaitingHello,
+            login_pending: false,
+        }
+    }
+
+    /// Returns side this state belongs to.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns current stage of connection.
+    pub fn phase(&self) -> ProtocolPhase {
+        self.phase
+    }
+
+    /// Checks `comm` received from peer and returns next state.
+    pub fn on_incoming(&self, comm: &Comm) -> Result<Self, CommError> {
+        self.next(self.role.peer(), comm)
+    }
+
+    /// Checks `comm` that is going to be sent to peer and returns next state.
+    pub fn on_outgoing(&self, comm: &Comm) -> Result<Self, CommError> {
+        self.next(self.role, comm)
+    }
+
+    fn next(&self, sender: Role, comm: &Comm) -> Result<Self, CommError> {
+        use ProtocolPhase::*;
+
+        let mut next = *self;
+        let valid = match (self.phase, sender, comm) {
+            (Closing, _, _) => false,
+
+            // Either side can close connection, see `Connection::close`.
+            (_, _, Comm::Disconnected(_)) => {
+                next.phase = Closing;
+                true
+            }
+
+            (AwaitingHello, Role::Client, Comm::Hello(_)) => true,
+            (AwaitingHello, Role::Server, Comm::Hello(_) | Comm::Limits(_)) => true,
+            (AwaitingHello, Role::Server, Comm::Connected { .. }) => {
+                next.phase = Connected;
+                true
+            }
+            // E.g. `CommError::VersionMismatch`.
+            (AwaitingHello, Role::Server, Comm::Rejected(_)) => {
+                next.phase = Closing;
+                true
+            }
+            (AwaitingHello, _, _) => false,
+
+            // Wrapped `Comm` follows the same rules, but can't be wrapped again.
+            (_, Role::Client, Comm::Request { request: inner, .. })
+            | (
+                _,
+                Role::Server,
+                Comm::Response {
+                    response: inner, ..
+                },
+            ) => {
+                return match **inner {
+                    Comm::Request { .. } | Comm::Response { .. } => {
+                        Err(CommError::InvalidOperation)
+                    }
+                    _ => self.next(sender, inner),
+                };
+            }
+
+            (_, _, Comm::Ping(_) | Comm::Pong(_)) => true,
+
+            (Connected, Role::Client, Comm::Login { .. } | Comm::Resume { .. }) => {
+                next.login_pending = true;
+                true
+            }
+            (Connected, Role::Client, Comm::Register { .. } | Comm::RequestAuthParams(_)) => true,
+            (Connected, Role::Server, Comm::AuthParams(_)) => true,
+            // Answer to `Register` doesn't log user in, only answer to `Login` or `Resume` does.
+            (Connected, Role::Server, Comm::User(_)) => {
+                if self.login_pending {
+                    next.phase = Authenticated;
+                    next.login_pending = false;
+                }
+                true
+            }
+            (Connected, Role::Server, Comm::Rejected(_)) => {
+                next.login_pending = false;
+                true
+            }
+            (Connected, _, _) => false,
+
+            (
+                Authenticated,
+                Role::Client,
+                Comm::Accepted
+                | Comm::RequestAuthParams(_)
+                | Comm::ChangePassword(_)
+                | Comm::Message(_)
+                | Comm::SealedMessage(_)
+                | Comm::Ack { .. }
+                | Comm::AckUpTo { .. }
+                | Comm::Resend { .. }
+                | Comm::PublishKey(_)
+                | Comm::RequestKey(_)
+                | Comm::AddInvitation(_)
+                | Comm::RemoveInvitation(_)
+                | Comm::AddFriend(_)
+                | Comm::RemoveFriend(_),
+            ) => true,
+            (
+                Authenticated,
+                Role::Server,
+                Comm::Accepted
+                | Comm::Rejected(_)
+                | Comm::AuthParams(_)
+                | Comm::User(_)
+                | Comm::Session { .. }
+                | Comm::Message(_)
+                | Comm::SealedMessage(_)
+                | Comm::Ack { .. }
+                | Comm::Deliver { .. }
+                | Comm::DeliverSealed { .. }
+                | Comm::DeliveryStart { .. }
+                | Comm::UserKey { .. }
+                | Comm::AddInvitation(_)
+                | Comm::RemoveInvitation(_)
+                | Comm::AddFriend(_)
+                | Comm::RemoveFriend(_),
+            ) => true,
+            (Authenticated, _, _) => false,
+        };
+
+        if valid {
+            Ok(next)
+        } else {
+            Err(CommError::InvalidOperation)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AuthParams, AuthVerifier, Hello, Message, PasswordChange, ServerLimits, SessionToken,
+        UserProfile,
+    };
+    use std::time::SystemTime;
+
+    /// Applies `comms` sent by `sender` to state of both sides and checks that they agree.
+    fn exchange(
+        client: ProtocolState,
+        server: ProtocolState,
+        sender: Role,
+        comms: &[Comm],
+    ) -> Result<(ProtocolState, ProtocolState), CommError> {
+        comms
+            .iter()
+            .try_fold((client, server), |(client, server), comm| {
+                let (client, server) = match sender {
+                    Role::Client => (client.on_outgoing(comm), server.on_incoming(comm)),
+                    Role::Server => (client.on_incoming(comm), server.on_outgoing(comm)),
+                };
+                assert_eq!(client.is_ok(), server.is_ok(), "{:?}", comm);
+                Ok((client?, server?))
+            })
+    }
+
+    fn new() -> (ProtocolState, ProtocolState) {
+        (
+            ProtocolState::new(Role::Client),
+            ProtocolState::new(Role::Server),
+        )
+    }
+
+    fn connected() -> (ProtocolState, ProtocolState) {
+        let (client, server) = new();
+        let (client, server) =
+            exchange(client, server, Role::Client, &[Comm::Hello(Hello::new(0))]).unwrap();
+        exchange(
+            client,
+            server,
+            Role::Server,
+            &[
+                Comm::Hello(Hello::new(0)),
+                Comm::Limits(ServerLimits::default()),
+                Comm::Connected {
+                    id: 1,
+                    nonce: [0; 32],
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    fn authenticated() -> (ProtocolState, ProtocolState) {
+        let (client, server) = connected();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Client,
+            &[Comm::Login {
+                id: 1,
+                proof: [0; 32],
+            }],
+        )
+        .unwrap();
+        exchange(client, server, Role::Server, &[user()]).unwrap()
+    }
+
+    fn user() -> Comm {
+        Comm::User(UserProfile::new(1))
+    }
+
+    /// Checks that `comm` sent by `sender` is rejected by both sides.
+    fn invalid(states: (ProtocolState, ProtocolState), sender: Role, comm: Comm) {
+        assert_eq!(
+            exchange(states.0, states.1, sender, &[comm]),
+            Err(CommError::InvalidOperation)
+        );
+    }
+
+    #[test]
+    fn handshake() {
+        let (client, server) = connected();
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+        assert_eq!(server.phase(), ProtocolPhase::Connected);
+        assert_eq!(client.role(), Role::Client);
+        assert_eq!(server.role(), Role::Server);
+    }
+
+    #[test]
+    fn hello_first() {
+        invalid(
+            new(),
+            Role::Client,
+            Comm::Login {
+                id: 1,
+                proof: [0; 32],
+            },
+        );
+        invalid(new(), Role::Client, Comm::Ping(1));
+        invalid(new(), Role::Client, Comm::AddFriend(2));
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let (client, server) = new();
+        let (client, server) =
+            exchange(client, server, Role::Client, &[Comm::Hello(Hello::new(0))]).unwrap();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::Rejected(CommError::VersionMismatch)],
+        )
+        .unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Closing);
+        assert_eq!(server.phase(), ProtocolPhase::Closing);
+    }
+
+    #[test]
+    fn limits_only_in_handshake() {
+        invalid(
+            connected(),
+            Role::Server,
+            Comm::Limits(ServerLimits::default()),
+        );
+        invalid(connected(), Role::Client, Comm::Hello(Hello::new(0)));
+        invalid(
+            authenticated(),
+            Role::Server,
+            Comm::Connected {
+                id: 2,
+                nonce: [0; 32],
+            },
+        );
+    }
+
+    #[test]
+    fn login() {
+        let (client, server) = authenticated();
+        assert_eq!(client.phase(), ProtocolPhase::Authenticated);
+        assert_eq!(server.phase(), ProtocolPhase::Authenticated);
+
+        // Session follows `User` after login.
+        exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::Session {
+                token: SessionToken::new(),
+                expires_at: SystemTime::now(),
+            }],
+        )
+        .unwrap();
+        invalid(
+            connected(),
+            Role::Server,
+            Comm::Session {
+                token: SessionToken::new(),
+                expires_at: SystemTime::now(),
+            },
+        );
+        invalid(
+            authenticated(),
+            Role::Client,
+            Comm::Login {
+                id: 1,
+                proof: [0; 32],
+            },
+        );
+    }
+
+    #[test]
+    fn auth_params_before_login() {
+        let (client, server) = connected();
+        let (client, server) =
+            exchange(client, server, Role::Client, &[Comm::RequestAuthParams(1)]).unwrap();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::AuthParams(AuthParams::new())],
+        )
+        .unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+        assert_eq!(server.phase(), ProtocolPhase::Connected);
+
+        invalid(connected(), Role::Server, Comm::RequestAuthParams(1));
+        invalid(
+            connected(),
+            Role::Client,
+            Comm::AuthParams(AuthParams::new()),
+        );
+
+        // Client that logged in with `Resume` needs them for `ChangePassword`.
+        let (client, server) = authenticated();
+        let (client, server) =
+            exchange(client, server, Role::Client, &[Comm::RequestAuthParams(1)]).unwrap();
+        exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::AuthParams(AuthParams::new())],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejected_login() {
+        let (client, server) = connected();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Client,
+            &[Comm::Resume {
+                token: SessionToken::new(),
+            }],
+        )
+        .unwrap();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::Rejected(CommError::BadLoginData)],
+        )
+        .unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+        // Only answer to pending login authenticates.
+        let (client, _) = exchange(client, server, Role::Server, &[user()]).unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+    }
+
+    #[test]
+    fn register_requires_login() {
+        let (client, server) = connected();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Client,
+            &[Comm::Register {
+                id: 1,
+                verifier: AuthVerifier::with_params("password", AuthParams::with_iterations(1)),
+                name: "Test".to_string(),
+            }],
+        )
+        .unwrap();
+        let (client, server) = exchange(client, server, Role::Server, &[user()]).unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+        assert_eq!(server.phase(), ProtocolPhase::Connected);
+        invalid((client, server), Role::Client, Comm::AddFriend(2));
+    }
+
+    #[test]
+    fn client_never_sends_user() {
+        invalid(connected(), Role::Client, user());
+        invalid(authenticated(), Role::Client, user());
+    }
+
+    #[test]
+    fn only_when_logged() {
+        let comms = || {
+            vec![
+                Comm::AddInvitation(2),
+                Comm::RemoveInvitation(2),
+                Comm::AddFriend(2),
+                Comm::RemoveFriend(2),
+                Comm::Message(Message::new("Hi".to_string(), 1, 2)),
+                Comm::ChangePassword(PasswordChange {
+                    params: AuthParams::new(),
+                    encrypted_key: [0; 32],
+                    proof: [0; 32],
+                }),
+                Comm::RequestKey(2),
+                Comm::Ack { from: 2, id: 1 },
+                Comm::AckUpTo { from: 2, seq: 1 },
+                Comm::Resend { from: 2, seq: 1 },
+            ]
+        };
+        let (client, server) = authenticated();
+        exchange(client, server, Role::Client, &comms()).unwrap();
+        for comm in comms() {
+            invalid(connected(), Role::Client, comm);
+        }
+    }
+
+    #[test]
+    fn accepted_only_when_logged() {
+        invalid(connected(), Role::Server, Comm::Accepted);
+        let (client, server) = authenticated();
+        exchange(client, server, Role::Server, &[Comm::Accepted]).unwrap();
+    }
+
+    #[test]
+    fn server_to_client_only() {
+        let deliver = || Comm::Deliver {
+            seq: 1,
+            message: Message::new("Hi".to_string(), 2, 1),
+        };
+        let (client, server) = authenticated();
+        exchange(client, server, Role::Server, &[deliver()]).unwrap();
+        invalid(authenticated(), Role::Client, deliver());
+        invalid(
+            authenticated(),
+            Role::Server,
+            Comm::AckUpTo { from: 2, seq: 1 },
+        );
+    }
+
+    #[test]
+    fn server_forwards_messages() {
+        let (client, server) = authenticated();
+        exchange(
+            client,
+            server,
+            Role::Server,
+            &[Comm::Message(Message::new("Hi".to_string(), 2, 1))],
+        )
+        .unwrap();
+        invalid(
+            connected(),
+            Role::Server,
+            Comm::Message(Message::new("Hi".to_string(), 2, 1)),
+        );
+    }
+
+    #[test]
+    fn disconnected() {
+        for sender in [Role::Client, Role::Server] {
+            for (client, server) in [new(), connected(), authenticated()] {
+                let (client, server) =
+                    exchange(client, server, sender, &[Comm::Disconnected(1)]).unwrap();
+                assert_eq!(client.phase(), ProtocolPhase::Closing);
+                assert_eq!(server.phase(), ProtocolPhase::Closing);
+                invalid((client, server), Role::Server, Comm::Accepted);
+                invalid((client, server), Role::Client, Comm::Ping(1));
+            }
+        }
+    }
+
+    #[test]
+    fn request_and_response() {
+        let request = |comm| Comm::Request {
+            id: 1,
+            request: Box::new(comm),
+        };
+        let response = |comm| Comm::Response {
+            id: 1,
+            response: Box::new(comm),
+        };
+
+        let (client, server) = authenticated();
+        exchange(client, server, Role::Client, &[request(Comm::AddFriend(2))]).unwrap();
+        exchange(client, server, Role::Server, &[response(Comm::Accepted)]).unwrap();
+
+        // Wrapped login works as well.
+        let (client, server) = connected();
+        let (client, server) = exchange(
+            client,
+            server,
+            Role::Client,
+            &[request(Comm::Login {
+                id: 1,
+                proof: [0; 32],
+            })],
+        )
+        .unwrap();
+        let (client, _) = exchange(client, server, Role::Server, &[response(user())]).unwrap();
+        assert_eq!(client.phase(), ProtocolPhase::Authenticated);
+
+        invalid(connected(), Role::Client, request(Comm::AddFriend(2)));
+        invalid(authenticated(), Role::Server, request(Comm::Accepted));
+        invalid(authenticated(), Role::Client, response(Comm::Accepted));
+        invalid(
+            authenticated(),
+            Role::Client,
+            request(request(Comm::AddFriend(2))),
+        );
+    }
+
+    #[test]
+    fn ping_after_handshake() {
+        for (client, server) in [connected(), authenticated()] {
+            exchange(client, server, Role::Client, &[Comm::Ping(1)]).unwrap();
+            exchange(client, server, Role::Server, &[Comm::Pong(1)]).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_keeps_state() {
+        let (client, _) = connected();
+        assert_eq!(
+            client.on_incoming(&Comm::Accepted),
+            Err(CommError::InvalidOperation)
+        );
+        assert_eq!(client.phase(), ProtocolPhase::Connected);
+    }
+}
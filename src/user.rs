@@ -4,25 +4,31 @@ use crate::{
 };
 use std::collections::HashSet;
 
-/// Represents user.
+/// Public part of user data. It is send to client after login, so it must never contain anything
+/// secret. Credentials are kept by server in `Account`.
 #[derive(Debug, PartialEq)]
-pub struct User {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserProfile {
     id: UserID,
-    password: String,
+    name: String,
     friends: HashSet<UserID>,
     invitations: HashSet<UserID>,
     // NOTE: In future we should have Time Zone information included.
 }
 
-impl User {
-    // id = 8, password = MAX_PASS_BYTE_LEN, friends and invitations booth 1 byte each.
-    const MIN_BYTE_LEN: usize = USER_ID_SIZE + crate::MAX_PASS_BYTE_LEN + 2;
+impl UserProfile {
+    // id = 8, name length, friends and invitations 1 byte each.
+    const MIN_BYTE_LEN: usize = USER_ID_SIZE + 3;
 
-    /// Creates empty user.
-    pub fn new(id: UserID, password: String) -> Self {
+    /// Size of profile with longest name and most friends and invitations that can be serialized.
+    pub const MAX_BYTE_LEN: usize =
+        UserProfile::MIN_BYTE_LEN + u8::MAX as usize * (1 + 2 * USER_ID_SIZE);
+
+    /// Creates empty profile without name.
+    pub fn new(id: UserID) -> Self {
         Self {
             id,
-            password,
+            name: String::new(),
             friends: HashSet::new(),
             invitations: HashSet::new(),
         }
@@ -33,19 +39,15 @@ impl User {
         self.id
     }
 
-    /// Returns user password.
-    pub fn password(&self) -> &String {
-        &self.password
+    /// Returns name displayed to other users. It can be empty.
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    /// Changes user password from current to new if current is the same as 'self.password'.
-    pub fn change_password(&mut self, new_password: String, current_password: &str) -> bool {
-        if self.password == current_password {
-            self.password = new_password;
-            true
-        } else {
-            false
-        }
+    /// Changes name displayed to other users. Name can't be longer than 255 bytes, otherwise it
+    /// can't be serialized.
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
     }
 
     /// Returns set of user friends IDs
@@ -89,92 +91,107 @@ impl User {
     }
 }
 
-impl Serialize for User {
-    type Item = User;
-
-    /// Tries to parse user data to u8 slice. It returns `()` on success and `SerializeError` on
-    /// any error.
-    fn serialize(&self, buffer: &mut [u8]) -> Result<(), SerializeError> {
-        if buffer.len() < User::MIN_BYTE_LEN {
-            Err(SerializeError::NotEnoughData)
-        } else {
-            let mut buffer_index =
-                crate::write_bytes_to_buffer(&mut buffer[..USER_ID_SIZE], &self.id.to_ne_bytes());
-
-            crate::write_bytes_to_buffer(&mut buffer[buffer_index..], self.password.as_bytes());
-            // It doesn't matter if password is shorter than maximum length. Rest space is
-            // reserved.
-            buffer_index += crate::MAX_PASS_BYTE_LEN;
-
-            // Now we have to write how many friends and invitations user have. Both are u8 (0-255)
-            // which should be more than enough for this simple communicator.
-            // TODO: Fix this algorithm to only count indexes that can fit in buffer. For now with
-            // just few contacts this isn't problem, but it can be.
-            buffer[buffer_index] = self.friends.len() as u8;
-            buffer_index += 1;
-            buffer[buffer_index] = self.invitations.len() as u8;
-            buffer_index += 1;
-
-            'id_write: for id_set in [self.friends.iter(), self.invitations.iter()] {
-                for id in id_set {
-                    if buffer.len() - buffer_index < USER_ID_SIZE {
-                        break 'id_write;
-                    }
-
-                    buffer_index += crate::write_bytes_to_buffer(
-                        &mut buffer[buffer_index..],
-                        &id.to_ne_bytes(),
-                    );
-                }
+impl Serialize for UserProfile {
+    fn encoded_len(&self) -> usize {
+        UserProfile::MIN_BYTE_LEN
+            + self.name.len()
+            + (self.friends.len() + self.invitations.len()) * USER_ID_SIZE
+    }
+
+    /// Tries to parse profile data to u8 slice. It returns number of bytes written on success and
+    /// `SerializeError` on any error.
+    fn serialize(&self, buffer: &mut [u8]) -> Result<usize, SerializeError> {
+        let name = self.name.as_bytes();
+        let ids_len = (self.friends.len() + self.invitations.len()) * USER_ID_SIZE;
+        // Name longer than u8 can't be described by length field.
+        if name.len() > u8::MAX as usize {
+            return Err(SerializeError::StringTooLong {
+                type_name: "UserProfile".into(),
+                offset: USER_ID_SIZE,
+                len: name.len(),
+                max: u8::MAX as usize,
+            });
+        }
+        for (i, &count) in [self.friends.len(), self.invitations.len()]
+            .iter()
+            .enumerate()
+        {
+            if count > u8::MAX as usize {
+                return Err(SerializeError::CountTooLarge {
+                    type_name: "UserProfile".into(),
+                    offset: USER_ID_SIZE + 1 + name.len() + i,
+                    count,
+                    max: u8::MAX as usize,
+                });
             }
+        }
+        if buffer.len() < UserProfile::MIN_BYTE_LEN + name.len() + ids_len {
+            return Err(SerializeError::NotEnoughData);
+        }
 
-            Ok(())
+        let mut buffer_index = crate::write_bytes_to_buffer(buffer, &self.id.to_le_bytes())?;
+        buffer[buffer_index] = name.len() as u8;
+        buffer_index += 1;
+        buffer_index += crate::write_bytes_to_buffer(&mut buffer[buffer_index..], name)?;
+
+        // Now we have to write how many friends and invitations user have. Both are u8 (0-255)
+        // which should be more than enough for this simple communicator.
+        buffer[buffer_index] = self.friends.len() as u8;
+        buffer_index += 1;
+        buffer[buffer_index] = self.invitations.len() as u8;
+        buffer_index += 1;
+
+        for id in self.friends.iter().chain(self.invitations.iter()) {
+            buffer_index +=
+                crate::write_bytes_to_buffer(&mut buffer[buffer_index..], &id.to_le_bytes())?;
         }
+
+        Ok(buffer_index)
     }
 
-    /// Tries to parse user data from u8 slice. It returns `Self` on success and `SerializeError` on
-    /// any error.
-    fn deserialize(buffer: &[u8]) -> Result<Self::Item, SerializeError> {
-        if buffer.len() < User::MIN_BYTE_LEN {
-            Err(SerializeError::NotEnoughData)
-        } else {
-            let id = crate::parse_id_from_bytes(&buffer[..USER_ID_SIZE]);
-            let mut buffer_index = USER_ID_SIZE + crate::MAX_PASS_BYTE_LEN;
-            let password =
-                crate::parse_string_from_bytes(&buffer[USER_ID_SIZE..buffer_index]).to_string();
-            let friends_count = buffer[buffer_index] as usize;
-            buffer_index += 1;
-            let invitations_count = buffer[buffer_index] as usize;
-            buffer_index += 1;
-            let mut friends = HashSet::new();
-            let mut invitations = HashSet::new();
-
-            // TODO: Add checks for buffer boundry so we cannot read outside buffer and cause
-            // panic.
-            let mut is_parsing_friends = true;
-            for count in [friends_count, invitations_count] {
-                for _ in 0..count {
-                    let contact_id = crate::parse_id_from_bytes(
-                        &buffer[buffer_index..buffer_index + USER_ID_SIZE],
-                    );
-                    if is_parsing_friends {
-                        friends.insert(contact_id);
-                    } else {
-                        invitations.insert(contact_id);
-                    }
-                    buffer_index += USER_ID_SIZE;
-                }
-
-                is_parsing_friends = false;
-            }
+    /// Tries to parse profile data from u8 slice. It returns `Self` and number of bytes read on
+    /// success and `SerializeError` on any error.
+    fn deserialize(buffer: &[u8]) -> Result<(Self, usize), SerializeError> {
+        if buffer.len() < UserProfile::MIN_BYTE_LEN {
+            return Err(SerializeError::NotEnoughData);
+        }
 
-            Ok(User {
-                id,
-                password,
-                friends,
-                invitations,
-            })
+        let id = crate::parse_id_from_bytes(buffer)?;
+        let mut buffer_index = USER_ID_SIZE;
+        let name_len = buffer[buffer_index] as usize;
+        buffer_index += 1;
+        let name = buffer
+            .get(buffer_index..buffer_index + name_len)
+            .ok_or(SerializeError::NotEnoughData)?;
+        let name = crate::parse_utf8(name, "UserProfile", buffer_index)?.to_string();
+        buffer_index += name_len;
+
+        let counts = buffer
+            .get(buffer_index..buffer_index + 2)
+            .ok_or(SerializeError::NotEnoughData)?;
+        let (friends_count, invitations_count) = (counts[0] as usize, counts[1] as usize);
+        buffer_index += 2;
+
+        let mut friends = HashSet::new();
+        let mut invitations = HashSet::new();
+        for (set, count) in [
+            (&mut friends, friends_count),
+            (&mut invitations, invitations_count),
+        ] {
+            for _ in 0..count {
+                let contact_id = crate::parse_id_from_bytes(&buffer[buffer_index..])?;
+                set.insert(contact_id);
+                buffer_index += USER_ID_SIZE;
+            }
         }
+
+        let user = UserProfile {
+            id,
+            name,
+            friends,
+            invitations,
+        };
+        Ok((user, buffer_index))
     }
 }
 
@@ -183,32 +200,86 @@ mod tests {
     use super::*;
 
     #[test]
-    fn change_password() {
-        let original_password = "abcd".to_string();
-        let new_password = "new_password".to_string();
-        let mut user = User::new(0, original_password.clone());
+    fn send_and_recive() {
+        let mut s = UserProfile::new(1);
+        s.set_name("Paweł".to_string());
+        s.add_friend(2);
+        s.add_friend(3);
+        s.add_invitation(10);
+        s.add_invitation(11);
 
-        // We shouldn't be able to change password if we don't provide old one correct
-        assert!(!user.change_password(new_password.clone(), &"bad_password".to_string()));
-        assert_eq!(user.password(), &original_password);
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        s.serialize(&mut buffer).unwrap();
+        let r = UserProfile::deserialize(&buffer).unwrap().0;
 
-        // When we provide proper old password then we can change it to new one
-        assert!(user.change_password(new_password, &original_password));
-        assert_ne!(user.password(), &original_password);
+        assert_eq!(s, r);
     }
 
     #[test]
-    fn send_and_recive() {
-        let mut s = User::new(1, "abcd".to_string());
+    fn truncated_buffer() {
+        let mut s = UserProfile::new(1);
+        s.set_name("Bob".to_string());
         s.add_friend(2);
-        s.add_friend(3);
         s.add_invitation(10);
-        s.add_invitation(11);
+        let len = UserProfile::MIN_BYTE_LEN + 3 + 2 * USER_ID_SIZE;
 
         let mut buffer = [0u8; crate::NET_BUFF_SIZE];
         s.serialize(&mut buffer).unwrap();
-        let r = User::deserialize(&buffer).unwrap();
+        for short in 0..len {
+            assert_eq!(
+                UserProfile::deserialize(&buffer[..short]),
+                Err(SerializeError::NotEnoughData)
+            );
+            assert_eq!(
+                s.serialize(&mut vec![0u8; short]),
+                Err(SerializeError::NotEnoughData)
+            );
+        }
+        assert_eq!(UserProfile::deserialize(&buffer[..len]).unwrap().0, s);
+    }
 
-        assert_eq!(s, r);
+    #[test]
+    fn name_too_long() {
+        let mut s = UserProfile::new(1);
+        s.set_name("a".repeat(256));
+        let mut buffer = [0u8; crate::NET_BUFF_SIZE];
+        assert_eq!(
+            s.serialize(&mut buffer),
+            Err(SerializeError::StringTooLong {
+                type_name: "UserProfile".into(),
+                offset: USER_ID_SIZE,
+                len: 256,
+                max: 255
+            })
+        );
+    }
+
+    #[test]
+    fn too_many_friends() {
+        let mut s = UserProfile::new(1);
+        s.set_name("Al".to_string());
+        for id in 0..256 {
+            s.add_invitation(id);
+        }
+        let mut buffer = vec![0u8; 4096];
+        assert_eq!(
+            s.serialize(&mut buffer),
+            Err(SerializeError::CountTooLarge {
+                type_name: "UserProfile".into(),
+                offset: USER_ID_SIZE + 1 + 2 + 1,
+                count: 256,
+                max: 255
+            })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut user = UserProfile::new(1);
+        user.add_friend(2);
+
+        let json = serde_json::to_string(&user).unwrap();
+        assert_eq!(serde_json::from_str::<UserProfile>(&json).unwrap(), user);
     }
 }